@@ -1,13 +1,45 @@
 use std::io::Read;
-use std::fs::File;
+use std::fs::{self, File};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use std::time::Duration;
 use std::collections::{HashMap, HashSet};
 
+use arc_swap::ArcSwap;
 use config::Config;
-use tap::Pipe;
+use csv::{ReaderBuilder, Trim};
+use rumqttc::QoS;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
 
+use crate::utils::logs::billing::export::{BillingExporter, Datev, JsonLines, Legacy};
+
+use self::billing::BillingRule;
+use self::log_file::{LogFile, Rotation};
+use self::sensor::{PowerSensor, Sensors};
 use self::slave::Slave;
+use self::user_group::UserGroup;
+use self::validation::{line_of, Report};
 
+pub mod billing;
+pub mod log_file;
+mod payload;
+pub mod reload;
+pub mod sensor;
 pub mod slave;
+pub mod user_group;
+pub mod validation;
+
+pub const URN_PREFIX: &str = "urn:fabaccess:resource:";
+
+const MAIN_CONFIG: &str = "spacermake.toml";
+const DEFAULT_BOOKING_TOPIC: &str = "fabaccess/log";
+
+///the currently active config, swapped out as a whole on reload
+pub type SharedConfig = Arc<ArcSwap<SpacerConfig>>;
 
 #[expect(clippy::module_name_repetitions, reason = "avoid name collision with `config` crate")]
 #[derive(Debug)]
@@ -15,122 +47,410 @@ pub struct SpacerConfig {
     pub slaves_by_master: HashMap<String, HashSet<String>>,
     pub slave_properties: HashMap<String, Slave>,
     pub machine_ids     : HashMap<String, String>,
+    pub sensors         : Sensors,
     pub data_user       : HashMap<String, UserData>,
+    pub user_groups     : HashMap<String, UserGroup>,
     pub data_machines   : HashMap<String, MachineData>,
-    pub billing_log     : String,
-    pub machine_log     : String,
-    pub debug_log       : String,
+    pub billing_rules   : HashMap<String, BillingRule>,
+    pub billing_log     : LogFile,
+    pub billing_exporter: Box<dyn BillingExporter>,
+    pub machine_log     : LogFile,
+    pub debug_log       : LogFile,
+    pub state_file      : String,
+    pub booking_topic   : String,
     pub mqtt_host       : String,
+    pub mqtt_port       : u16,
+    pub mqtt_client_id  : String,
+    pub mqtt_qos        : QoS,
+    pub mqtt_tls        : Option<MqttTls>,
     pub mqtt_username   : Option<String>,
     pub mqtt_password   : Option<String>,
     pub fabaccess_host  : String,
     pub fabaccess_port  : u16,
-    pub hide_unbooked   : bool
+    pub fabaccess_username: Option<String>,
+    pub fabaccess_password: Option<String>,
+    pub hide_unbooked   : bool,
+    pub http_bind       : IpAddr,
+    pub http_port       : u16,
+    pub http_tls        : Option<HttpTls>,
+    pub www_dir         : String,
+    ///serve the unauthenticated health and metrics endpoints
+    pub http_monitoring : bool,
+    ///web UI logins expire after this long without a request
+    pub session_timeout : Duration,
+    pub slave_confirmation_timeout: Duration,
+    pub slave_max_retries: u32,
+    pub source_files    : Vec<String>
+}
+
+///paths to PEM files, TLS is used whenever a CA is configured
+#[derive(Debug)]
+pub struct MqttTls {
+    pub ca: String,
+    pub client_auth: Option<(String, String)>
+}
+
+///paths to PEM files for serving the web UI over HTTPS
+#[derive(Debug)]
+pub struct HttpTls {
+    pub cert: String,
+    pub key: String
+}
+
+impl HttpTls {
+    ///reads and checks the certificate and key, done during validation already so a bad file can't stop the web UI later
+    pub fn server_config(&self) -> Result<ServerConfig, String> {
+        let certs = CertificateDer::pem_file_iter(&self.cert)
+            .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+            .map_err(|error| format!("can't read certificate {} ~~ {error}", self.cert))?;
+
+        if certs.is_empty() {
+            return Err(format!("no certificate found in {}", self.cert));
+        }
+
+        let key = PrivateKeyDer::from_pem_file(&self.key)
+            .map_err(|error| format!("can't read private key {} ~~ {error}", self.key))?;
+
+        ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|error| format!("certificate and key don't fit ~~ {error}"))
+    }
 }
 
 #[derive(Debug)]
 pub struct UserData {
     pub id: Option<i32>,
-    pub to_be_used: bool
+    pub to_be_used: bool,
+    ///pricing, see user_groups.toml
+    pub group: Option<String>
 }
 
 #[derive(Debug)]
 pub struct MachineData {
     pub id: Option<i32>,
     pub to_be_used: bool,
-    pub power_sense: bool, //1 = runtime, 0 = booked time
-    pub divider: i32
+    ///from the powerSense and divider columns, overridden by billing_rules.toml
+    pub billing_rule: BillingRule
 }
 
 impl SpacerConfig {
-    pub fn load() -> Self {
-        let config = Config::builder()
+    ///the config is only returned if the report contains no errors
+    pub fn load() -> (Option<Self>, Report) {
+        let mut report = Report::default();
+
+        let config = match Config::builder()
             .add_source(config::File::with_name("spacermake"))
             .add_source(config::Environment::default())
             .build()
-            .expect("failed to load paths");
-        
-        let slaves_by_master = open_or_create_file(&config, "SLAVES_BY_MASTER") // master-slave_relations.toml
-            .pipe_as_ref(toml::from_str)
-            .expect("failed to load SLAVES_BY_MASTER");
-        
-        let slave_properties = open_or_create_file(&config, "SLAVE_PROPERTIES") // slave_properties.toml
-            .pipe_as_ref(toml::from_str)
-            .expect("failed to load SLAVE_PROPERTIES");
-        
-        let machine_ids = open_or_create_file(&config, "MACHINE_IDS") // /root/fabfire/config.toml
-            .pipe_as_ref(toml::from_str::<toml::Table>)
-            .expect("failed to load MACHINE_IDS")
-            ["readers"]
-            .as_table()
-            .unwrap()
-            .iter()
-            .map(|(_key, value)| {
-                let entry = value.as_table().unwrap();
-                (
-                    entry["machine"].as_str().unwrap().replace("urn:fabaccess:resource:", ""),
-                    entry["id"].as_str().unwrap().into()
-                )
-            })
-            .collect();
-        
-        let data_user = open_or_create_file(&config, "DATA_USER") // DataUser.csv
-            .lines()
-            .map(|line| {
-                let mut splits = line.split(',');
-                
-                let name = splits.next().unwrap().to_owned();
-    
-                let ud = UserData {
-                    id        : splits.next().unwrap().parse       ().ok(),
-                    to_be_used: splits.next().unwrap().parse::<i32>().unwrap_or(1) == 1,
-                };
-                
-                (name, ud)
-            })
-            .collect();
+        {
+            Ok(config) => config,
+            Err(error) => {
+                report.error(MAIN_CONFIG, None, error);
+                return (None, report);
+            }
+        };
 
-        let data_machines = open_or_create_file(&config, "DATA_MACHINES") // DataMachines.csv
-            .lines()
-            .map(|line| {
-                let mut splits = line.split(',');
-                
-                let name = splits.next().unwrap().to_owned();
-                let md = MachineData {
-                    id         : splits.next().unwrap().parse       ().ok(),
-                    to_be_used : splits.next().unwrap().parse::<i32>().unwrap_or(1) == 1,
-                    power_sense: splits.next().unwrap().parse::<i32>().unwrap()     == 1,
-                    divider    : splits.next().unwrap().parse       ().unwrap()
-                };
-                
-                (name, md)
-            })
+        let source_files = ["SLAVES_BY_MASTER", "SLAVE_PROPERTIES", "MACHINE_IDS", "SENSORS", "BILLING_RULES", "USER_GROUPS", "DATA_USER", "DATA_MACHINES"]
+            .map(|key| path_of(&config, key))
+            .into_iter()
+            .chain([MAIN_CONFIG.to_owned()])
             .collect();
-        
-        Self {
+
+        let slaves_by_master = load_file(&config, &mut report, "SLAVES_BY_MASTER") // master-slave_relations.toml
+            .and_then(|(path, content)| parse_toml(&mut report, &path, &content))
+            .unwrap_or_default();
+
+        let slave_properties = load_file(&config, &mut report, "SLAVE_PROPERTIES") // slave_properties.toml
+            .and_then(|(path, content)| parse_toml(&mut report, &path, &content))
+            .unwrap_or_default();
+
+        let machine_ids = load_file(&config, &mut report, "MACHINE_IDS") // /root/fabfire/config.toml
+            .map(|(path, content)| parse_machine_ids(&mut report, &path, &content))
+            .unwrap_or_default();
+
+        let sensors = load_file(&config, &mut report, "SENSORS") // sensors.toml
+            .and_then(|(path, content)| parse_toml(&mut report, &path, &content))
+            .unwrap_or_default();
+
+        check_sensors(&mut report, &path_of(&config, "SENSORS"), &sensors);
+
+        let billing_rules = load_file(&config, &mut report, "BILLING_RULES") // billing_rules.toml
+            .and_then(|(path, content)| parse_toml(&mut report, &path, &content))
+            .unwrap_or_default();
+
+        check_billing_rules(&mut report, &path_of(&config, "BILLING_RULES"), &billing_rules);
+
+        let user_groups = load_file(&config, &mut report, "USER_GROUPS") // user_groups.toml
+            .and_then(|(path, content)| parse_toml(&mut report, &path, &content))
+            .unwrap_or_default();
+
+        check_user_groups(&mut report, &path_of(&config, "USER_GROUPS"), &user_groups);
+
+        let csv_format = CsvFormat::load(&config, &mut report);
+
+        let data_user = load_file(&config, &mut report, "DATA_USER") // DataUser.csv
+            .map(|(path, content)| parse_data_user(&mut report, &path, &content, csv_format))
+            .unwrap_or_default();
+
+        let data_machines = load_file(&config, &mut report, "DATA_MACHINES") // DataMachines.csv
+            .map(|(path, content)| parse_data_machines(&mut report, &path, &content, csv_format))
+            .unwrap_or_default();
+
+        check_group_references(&mut report, &path_of(&config, "DATA_USER"), &data_user, &user_groups);
+
+        check_references(
+            &mut report,
+            [&path_of(&config, "SLAVES_BY_MASTER"), &path_of(&config, "MACHINE_IDS")],
+            &slaves_by_master,
+            &slave_properties,
+            &machine_ids
+        );
+
+        let compress_logs      = optional_setting(&config, &mut report, "LOG_COMPRESSION").unwrap_or(false);
+        let billing_log        = load_billing_log(&config, &mut report, compress_logs);
+        let billing_exporter   = load_billing_exporter(&config, &mut report);
+        let machine_log        = load_log_file(&config, &mut report, "MACHINE_LOG", compress_logs);
+        let debug_log          = load_log_file(&config, &mut report, "DEBUG_LOG", compress_logs);
+        let state_file         = setting(&config, &mut report, "STATE_FILE");
+        let booking_topic      = optional_setting(&config, &mut report, "BOOKING_TOPIC").unwrap_or_else(|| DEFAULT_BOOKING_TOPIC.to_owned());
+        let mqtt_host          = setting(&config, &mut report, "MQTT_HOST");
+        let mqtt_port          = optional_setting(&config, &mut report, "MQTT_PORT").unwrap_or(1883);
+        let mqtt_client_id     = optional_setting(&config, &mut report, "MQTT_CLIENT_ID").unwrap_or_else(|| "spacermake".to_owned());
+        let mqtt_qos           = load_qos(&config, &mut report);
+        let mqtt_tls           = load_tls(&config, &mut report);
+        let mqtt_username      = optional_setting(&config, &mut report, "MQTT_USERNAME");
+        let mqtt_password      = optional_setting(&config, &mut report, "MQTT_PASSWORD");
+        let fabaccess_host     = setting(&config, &mut report, "FABACCESS_HOST");
+        let fabaccess_port     = setting(&config, &mut report, "FABACCESS_PORT");
+        let fabaccess_username = optional_setting(&config, &mut report, "FABACCESS_USERNAME");
+        let fabaccess_password = optional_setting(&config, &mut report, "FABACCESS_PASSWORD");
+        let hide_unbooked      = setting(&config, &mut report, "HIDE_UNBOOKED");
+        let http_bind          = optional_setting(&config, &mut report, "HTTP_BIND").unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let http_tls           = load_http_tls(&config, &mut report);
+        let http_port          = optional_setting(&config, &mut report, "HTTP_PORT").unwrap_or(if http_tls.is_some() { 443 } else { 80 });
+        let www_dir            = load_www_dir(&config, &mut report);
+        let http_monitoring    = optional_setting(&config, &mut report, "HTTP_MONITORING").unwrap_or(true);
+        let session_timeout    = optional_duration(&config, &mut report, "SESSION_TIMEOUT").unwrap_or(Duration::from_secs(30 * 60));
+        let slave_confirmation_timeout = optional_duration(&config, &mut report, "SLAVE_CONFIRMATION_TIMEOUT").unwrap_or(Duration::from_secs(5));
+        let slave_max_retries  = optional_setting(&config, &mut report, "SLAVE_MAX_RETRIES").unwrap_or(3);
+
+        for problem in billing_exporter.problems(&data_user, &data_machines) {
+            report.error(MAIN_CONFIG, Some("BILLING_FORMAT".into()), problem);
+        }
+
+        if report.has_errors() {
+            return (None, report);
+        }
+
+        let assemble = || Some(Self {
             slaves_by_master,
             slave_properties,
             machine_ids,
+            sensors,
             data_user,
+            user_groups,
             data_machines,
-            billing_log   : config.get("BILLING_LOG").unwrap(),
-            machine_log   : config.get("MACHINE_LOG").unwrap(),
-            debug_log     : config.get("DEBUG_LOG").unwrap(),
-            mqtt_host     : config.get("MQTT_HOST").unwrap(),
-            mqtt_username : config.get("MQTT_USERNAME").ok(),
-            mqtt_password : config.get("MQTT_PASSWORD").ok(),
-            fabaccess_host: config.get("FABACCESS_HOST").unwrap(),
-            fabaccess_port: config.get("FABACCESS_PORT").unwrap(),
-            hide_unbooked : config.get("HIDE_UNBOOKED").unwrap()
+            billing_rules,
+            billing_log   : billing_log?,
+            billing_exporter,
+            machine_log   : machine_log?,
+            debug_log     : debug_log?,
+            state_file    : state_file?,
+            booking_topic,
+            mqtt_host     : mqtt_host?,
+            mqtt_port,
+            mqtt_client_id,
+            mqtt_qos,
+            mqtt_tls,
+            mqtt_username,
+            mqtt_password,
+            fabaccess_host: fabaccess_host?,
+            fabaccess_port: fabaccess_port?,
+            fabaccess_username,
+            fabaccess_password,
+            hide_unbooked : hide_unbooked?,
+            http_bind,
+            http_port,
+            http_tls,
+            www_dir,
+            http_monitoring,
+            session_timeout,
+            slave_confirmation_timeout,
+            slave_max_retries,
+            source_files
+        });
+
+        (assemble(), report)
+    }
+}
+
+fn setting<T: DeserializeOwned>(config: &Config, report: &mut Report, key: &str) -> Option<T> {
+    config
+        .get(key)
+        .map_err(|error| report.error(MAIN_CONFIG, Some(key.to_owned()), error))
+        .ok()
+}
+
+fn optional_setting<T: DeserializeOwned>(config: &Config, report: &mut Report, key: &str) -> Option<T> {
+    match config.get(key) {
+        Ok(value) => Some(value),
+        Err(config::ConfigError::NotFound(_)) => None,
+        Err(error) => {
+            report.error(MAIN_CONFIG, Some(key.to_owned()), error);
+            None
         }
     }
 }
 
-fn open_or_create_file(config: &Config, key: &str) -> String {
-    let path = config
+///human-readable durations like "5s" or "3m"
+fn optional_duration(config: &Config, report: &mut Report, key: &str) -> Option<Duration> {
+    let text: String = optional_setting(config, report, key)?;
+
+    humantime::parse_duration(&text)
+        .map_err(|error| report.error(MAIN_CONFIG, Some(key.to_owned()), error))
+        .ok()
+}
+
+fn load_qos(config: &Config, report: &mut Report) -> QoS {
+    let level = optional_setting::<u8>(config, report, "MQTT_QOS").unwrap_or(0);
+
+    rumqttc::qos(level)
+        .map_err(|error| report.error(MAIN_CONFIG, Some("MQTT_QOS".into()), error))
+        .unwrap_or(QoS::AtMostOnce)
+}
+
+fn load_tls(config: &Config, report: &mut Report) -> Option<MqttTls> {
+    let ca = optional_setting::<String>(config, report, "MQTT_TLS_CA");
+    let cert = optional_setting::<String>(config, report, "MQTT_TLS_CERT");
+    let key = optional_setting::<String>(config, report, "MQTT_TLS_KEY");
+
+    let client_auth = match (cert, key) {
+        (Some(cert), Some(key)) => Some((cert, key)),
+        (None, None) => None,
+        _ => {
+            report.error(MAIN_CONFIG, Some("MQTT_TLS_CERT".into()), "MQTT_TLS_CERT and MQTT_TLS_KEY must be set together");
+            None
+        }
+    };
+
+    let Some(ca) = ca else {
+        if client_auth.is_some() {
+            report.error(MAIN_CONFIG, Some("MQTT_TLS_CA".into()), "client certificates require MQTT_TLS_CA");
+        }
+        return None;
+    };
+
+    let files = [Some(&ca), client_auth.as_ref().map(|(cert, _)| cert), client_auth.as_ref().map(|(_, key)| key)];
+    for path in files.into_iter().flatten() {
+        if let Err(error) = fs::metadata(path) {
+            report.error(path, None, format!("can't read TLS file ~~ {error}"));
+        }
+    }
+
+    Some(MqttTls { ca, client_auth })
+}
+
+fn load_http_tls(config: &Config, report: &mut Report) -> Option<HttpTls> {
+    let cert = optional_setting::<String>(config, report, "HTTP_TLS_CERT");
+    let key = optional_setting::<String>(config, report, "HTTP_TLS_KEY");
+
+    let (cert, key) = match (cert, key) {
+        (Some(cert), Some(key)) => (cert, key),
+        (None, None) => return None,
+        _ => {
+            report.error(MAIN_CONFIG, Some("HTTP_TLS_CERT".into()), "HTTP_TLS_CERT and HTTP_TLS_KEY must be set together");
+            return None;
+        }
+    };
+
+    let tls = HttpTls { cert, key };
+    if let Err(error) = tls.server_config() {
+        report.error(MAIN_CONFIG, Some("HTTP_TLS_CERT".into()), error);
+    }
+
+    Some(tls)
+}
+
+///static files of the web UI, relative to the working directory unless absolute
+fn load_www_dir(config: &Config, report: &mut Report) -> String {
+    let www_dir = optional_setting(config, report, "WWW_DIR").unwrap_or_else(|| "www".to_owned());
+
+    if !fs::metadata(&www_dir).is_ok_and(|metadata| metadata.is_dir()) {
+        report.warning(&www_dir, None, "static files directory not found, the web UI will lack its styles");
+    }
+
+    www_dir
+}
+
+///`<KEY>_ROTATION` and `<KEY>_RETENTION` go along with the path
+fn load_log_file(config: &Config, report: &mut Report, key: &str, compress: bool) -> Option<LogFile> {
+    let rotation_key = format!("{key}_ROTATION");
+    let rotation = optional_setting::<String>(config, report, &rotation_key)
+        .and_then(|text| {
+            Rotation::parse(&text)
+                .map_err(|error| report.error(MAIN_CONFIG, Some(rotation_key), error))
+                .ok()
+        })
+        .unwrap_or(Rotation::Never);
+
+    let retention = optional_duration(config, report, &format!("{key}_RETENTION"));
+
+    Some(LogFile {
+        path: setting(config, report, key)?,
+        rotation,
+        compress,
+        retention
+    })
+}
+
+///billing logs are legally relevant, so they're rotated monthly and never deleted
+fn load_billing_log(config: &Config, report: &mut Report, compress: bool) -> Option<LogFile> {
+    for key in ["BILLING_LOG_ROTATION", "BILLING_LOG_RETENTION"] {
+        if config.get_string(key).is_ok() {
+            report.error(MAIN_CONFIG, Some(key.to_owned()), "billing logs are always rotated monthly and never deleted");
+        }
+    }
+
+    Some(LogFile {
+        path: setting(config, report, "BILLING_LOG")?,
+        rotation: Rotation::Monthly,
+        compress,
+        retention: None
+    })
+}
+
+fn load_billing_exporter(config: &Config, report: &mut Report) -> Box<dyn BillingExporter> {
+    let format = optional_setting::<String>(config, report, "BILLING_FORMAT").unwrap_or_else(|| "legacy".to_owned());
+
+    match format.as_str() {
+        "legacy" => Box::new(Legacy {
+            quelle      : optional_setting(config, report, "BILLING_QUELLE").unwrap_or_else(|| "allgemeiner Beleg".to_owned()),
+            brutto_netto: optional_setting(config, report, "BILLING_BRUTTO_NETTO").unwrap_or(2),
+            rechnungstyp: optional_setting(config, report, "BILLING_RECHNUNGSTYP").unwrap_or(0)
+        }),
+        "datev" => Box::new(Datev {
+            gegenkonto   : setting(config, report, "BILLING_GEGENKONTO").unwrap_or_default(),
+            bu_schluessel: optional_setting(config, report, "BILLING_BU_SCHLUESSEL").unwrap_or_default(),
+            prices       : setting(config, report, "BILLING_PRICES").unwrap_or_default()
+        }),
+        "json_lines" => Box::new(JsonLines),
+        _ => {
+            report.error(MAIN_CONFIG, Some("BILLING_FORMAT".into()), format!("unknown format `{format}`, expected legacy, datev or json_lines"));
+            Box::new(JsonLines)
+        }
+    }
+}
+
+fn path_of(config: &Config, key: &str) -> String {
+    config
         .get_string(key)
-        .unwrap();
-    
+        .unwrap_or_else(|_| key.to_owned())
+}
+
+fn load_file(config: &Config, report: &mut Report, key: &str) -> Option<(String, String)> {
+    let path: String = setting(config, report, key)?;
+
     let mut out = String::new();
 
     File::options()
@@ -139,9 +459,230 @@ fn open_or_create_file(config: &Config, key: &str) -> String {
         .create(true)
         .truncate(false)
         .open(&path)
-        .unwrap_or_else(|error| panic!("failed to open file {path} ~~ {error}"))
-        .read_to_string(&mut out)
-        .unwrap();
-    
-    out
-}
\ No newline at end of file
+        .and_then(|mut file| file.read_to_string(&mut out))
+        .map_err(|error| report.error(&path, None, format!("failed to open file ~~ {error}")))
+        .ok()?;
+
+    Some((path, out))
+}
+
+fn parse_toml<T: DeserializeOwned>(report: &mut Report, path: &str, content: &str) -> Option<T> {
+    toml::from_str(content)
+        .map_err(|error| {
+            let location = error.span().map(|span| line_of(content, span.start));
+            report.error(path, location, error.message());
+        })
+        .ok()
+}
+
+fn parse_machine_ids(report: &mut Report, path: &str, content: &str) -> HashMap<String, String> {
+    let Some(table) = parse_toml::<toml::Table>(report, path, content) else {
+        return HashMap::new();
+    };
+
+    let Some(readers) = table.get("readers").and_then(toml::Value::as_table) else {
+        report.error(path, None, "missing [readers] table");
+        return HashMap::new();
+    };
+
+    readers
+        .iter()
+        .filter_map(|(key, entry)| {
+            let location = Some(format!("readers.{key}"));
+            let machine = entry.get("machine").and_then(toml::Value::as_str);
+            let id = entry.get("id").and_then(toml::Value::as_str);
+
+            match (machine, id) {
+                (Some(machine), Some(id)) => Some((machine.replace(URN_PREFIX, ""), id.to_owned())),
+                (None, _) => { report.error(path, location, "missing string `machine`"); None },
+                (_, None) => { report.error(path, location, "missing string `id`"); None }
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+struct UserRow {
+    name: String,
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    id: Option<i32>,
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    to_be_used: Option<i32>,
+    #[serde(default)]
+    group: Option<String>
+}
+
+#[derive(Debug, Deserialize)]
+struct MachineRow {
+    name: String,
+    #[serde(deserialize_with = "csv::invalid_option")]
+    id: Option<i32>,
+    #[serde(deserialize_with = "csv::invalid_option")]
+    to_be_used: Option<i32>,
+    power_sense: i32,
+    divider: i32
+}
+
+///how the office exports DataUser.csv and DataMachines.csv
+#[derive(Debug, Clone, Copy)]
+struct CsvFormat {
+    delimiter: u8,
+    has_headers: bool
+}
+
+impl CsvFormat {
+    fn load(config: &Config, report: &mut Report) -> Self {
+        let delimiter = optional_setting::<String>(config, report, "CSV_DELIMITER")
+            .map_or(Some(b','), |delimiter| match delimiter.as_bytes() {
+                [byte] => Some(*byte),
+                _ => {
+                    report.error(MAIN_CONFIG, Some("CSV_DELIMITER".into()), format!("`{delimiter}` is not a single-byte character"));
+                    None
+                }
+            })
+            .unwrap_or(b',');
+
+        Self {
+            delimiter,
+            has_headers: optional_setting(config, report, "CSV_HAS_HEADERS").unwrap_or(false)
+        }
+    }
+}
+
+///rows that fail to parse are reported and skipped, entirely empty rows are ignored
+fn parse_csv<Row: DeserializeOwned>(report: &mut Report, path: &str, content: &str, format: CsvFormat) -> Vec<(String, Row)> {
+    ReaderBuilder::new()
+        .delimiter(format.delimiter)
+        .has_headers(format.has_headers)
+        .flexible(true)
+        .trim(Trim::All)
+        .from_reader(content.as_bytes())
+        .into_records()
+        .filter_map(|record| {
+            let record = record
+                .map_err(|error| {
+                    let location = error.position().map(|position| format!("line {}", position.line()));
+                    report.error(path, location, error);
+                })
+                .ok()?;
+
+            if record.iter().all(str::is_empty) {
+                return None;
+            }
+
+            let location = record.position().map_or_else(String::new, |position| format!("line {}", position.line()));
+
+            record
+                .deserialize(None)
+                .map_err(|error| report.error(path, Some(location.clone()), error))
+                .ok()
+                .map(|row| (location, row))
+        })
+        .collect()
+}
+
+fn parse_data_user(report: &mut Report, path: &str, content: &str, format: CsvFormat) -> HashMap<String, UserData> {
+    parse_csv::<UserRow>(report, path, content, format)
+        .into_iter()
+        .map(|(_location, row)| {
+            let ud = UserData {
+                id        : row.id,
+                to_be_used: row.to_be_used.unwrap_or(1) == 1,
+                group     : row.group.filter(|group| !group.is_empty())
+            };
+
+            (row.name, ud)
+        })
+        .collect()
+}
+
+fn parse_data_machines(report: &mut Report, path: &str, content: &str, format: CsvFormat) -> HashMap<String, MachineData> {
+    parse_csv::<MachineRow>(report, path, content, format)
+        .into_iter()
+        .filter_map(|(location, row)| {
+            if row.divider == 0 {
+                report.error(path, Some(location), "divider must not be 0");
+                return None;
+            }
+
+            let md = MachineData {
+                id          : row.id,
+                to_be_used  : row.to_be_used.unwrap_or(1) == 1,
+                billing_rule: BillingRule::legacy(row.power_sense, row.divider)
+            };
+
+            Some((row.name, md))
+        })
+        .collect()
+}
+
+fn check_references(
+    report: &mut Report,
+    [relations_path, machine_ids_path]: [&str; 2],
+    slaves_by_master: &HashMap<String, HashSet<String>>,
+    slave_properties: &HashMap<String, Slave>,
+    machine_ids: &HashMap<String, String>
+) {
+    for (master, slaves) in slaves_by_master {
+        if let Err(problem) = sensor::check_machine_name(master) {
+            report.error(relations_path, Some(master.clone()), problem);
+        }
+
+        for slave in slaves {
+            if !slave_properties.contains_key(slave) {
+                report.error(relations_path, Some(master.clone()), format!("slave `{slave}` has no entry in slave properties"));
+            }
+        }
+
+        if !machine_ids.contains_key(master) {
+            report.warning(machine_ids_path, None, format!("master `{master}` has no reader"));
+        }
+    }
+}
+
+fn check_sensors(report: &mut Report, path: &str, sensors: &Sensors) {
+    let power_sensors = sensors
+        .power
+        .machines
+        .iter()
+        .map(|(machine, sensor)| (format!("power.machines.{machine}"), sensor))
+        .chain(sensors.power.default.iter().map(|sensor| ("power.default".to_owned(), sensor)));
+
+    for (key, sensor) in power_sensors {
+        if let PowerSensor::Watts { threshold, off_threshold: Some(off_threshold), .. } = sensor
+            && off_threshold > threshold
+        {
+            report.error(path, Some(key), format!("off_threshold {off_threshold} is above threshold {threshold}"));
+        }
+    }
+
+    for (key, problem) in sensors.power.problems("power").into_iter().chain(sensors.energy.problems("energy")) {
+        report.error(path, Some(key), problem);
+    }
+}
+
+fn check_billing_rules(report: &mut Report, path: &str, billing_rules: &HashMap<String, BillingRule>) {
+    for (machine, rule) in billing_rules {
+        if let Err(problem) = rule.validate() {
+            report.error(path, Some(machine.clone()), problem);
+        }
+    }
+}
+
+fn check_user_groups(report: &mut Report, path: &str, user_groups: &HashMap<String, UserGroup>) {
+    for (name, group) in user_groups {
+        if let Err(problem) = group.validate() {
+            report.error(path, Some(name.clone()), problem);
+        }
+    }
+}
+
+fn check_group_references(report: &mut Report, data_user_path: &str, data_user: &HashMap<String, UserData>, user_groups: &HashMap<String, UserGroup>) {
+    for (user, user_data) in data_user {
+        if let Some(group) = &user_data.group
+            && !user_groups.contains_key(group)
+        {
+            report.warning(data_user_path, Some(user.clone()), format!("unknown group `{group}`, billing at regular prices"));
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use std::ops::Div;
+
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::utils::booking::Booking;
+
+///how a booking turns into the quantity on the bill, see billing_rules.toml
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BillingRule {
+    pub basis: BillingBasis,
+    ///units (minutes or kWh) per billed piece, pieces get rounded up
+    #[serde(default = "default_divider")]
+    pub divider: f64,
+    ///usage from `from` on counts with `rate`, usage below the first tier counts fully
+    #[serde(default)]
+    pub tiers: Vec<Tier>,
+    ///pieces billed at least, fees excluded
+    #[serde(default)]
+    pub minimum: i32,
+    ///pieces billed per booking on top of the usage
+    #[serde(default)]
+    pub base_fee: i32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BillingBasis {
+    ///minutes between booking and release
+    BookedTime,
+    ///minutes the machine was running
+    Runtime,
+    ///kWh consumed, needs an energy meter
+    Energy,
+    ///runtime or this fraction of the booked time, whichever is greater. in minutes
+    RuntimeOrBooked(f64),
+    ///nothing but the base fee
+    Flat
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tier {
+    pub from: f64,
+    pub rate: f64
+}
+
+fn default_divider() -> f64 {
+    1.0
+}
+
+impl Default for BillingRule {
+    fn default() -> Self {
+        Self {
+            basis: BillingBasis::Runtime,
+            divider: default_divider(),
+            tiers: Vec::new(),
+            minimum: 0,
+            base_fee: 0
+        }
+    }
+}
+
+impl BillingRule {
+    ///what DataMachines.csv's powerSense and divider columns used to mean
+    pub fn legacy(power_sense: i32, divider: i32) -> Self {
+        let basis = match power_sense {
+            1 => BillingBasis::Runtime,
+            2 => BillingBasis::Energy,
+            _ => BillingBasis::BookedTime
+        };
+
+        Self {
+            basis,
+            divider: f64::from(divider),
+            ..Self::default()
+        }
+    }
+
+    ///the problem with this rule, if any
+    pub fn validate(&self) -> Result<(), String> {
+        if self.divider <= 0.0 {
+            return Err(format!("divider must be positive, got {}", self.divider));
+        }
+
+        if let BillingBasis::RuntimeOrBooked(fraction) = self.basis
+            && !(0.0..=1.0).contains(&fraction)
+        {
+            return Err(format!("booked time fraction must be between 0 and 1, got {fraction}"));
+        }
+
+        if self.tiers.iter().any(|tier| tier.rate < 0.0) {
+            return Err("tier rates must not be negative".into());
+        }
+
+        if !self.tiers.is_sorted_by(|a, b| a.from < b.from) {
+            return Err("tiers must be in ascending order of `from`".into());
+        }
+
+        if self.minimum < 0 || self.base_fee < 0 {
+            return Err("minimum and base_fee must not be negative".into());
+        }
+
+        Ok(())
+    }
+
+    pub fn quantity(&self, machine: &str, booking: &Booking) -> i32 {
+        let minutes = |duration: std::time::Duration| duration.as_secs_f64().div(60.0);
+
+        let usage = match self.basis {
+            BillingBasis::BookedTime => minutes(booking.booked_duration()),
+            BillingBasis::Runtime => minutes(booking.total_runtime()),
+            BillingBasis::Energy => booking.consumed_energy().unwrap_or_else(|| {
+                warn!("no energy readings for {machine}, billing no usage");
+                0.0
+            }),
+            BillingBasis::RuntimeOrBooked(fraction) => minutes(booking.total_runtime()).max(minutes(booking.booked_duration()) * fraction),
+            BillingBasis::Flat => 0.0
+        };
+
+        let pieces = self
+            .weighted(usage)
+            .div(self.divider)
+            .ceil()
+            as i32;
+
+        pieces.max(self.minimum) + self.base_fee
+    }
+
+    fn weighted(&self, usage: f64) -> f64 {
+        let Some(first) = self.tiers.first() else {
+            return usage;
+        };
+
+        let ends = self.tiers
+            .iter()
+            .skip(1)
+            .map(|tier| tier.from)
+            .chain([f64::INFINITY]);
+
+        self.tiers
+            .iter()
+            .zip(ends)
+            .map(|(tier, end)| (usage.min(end) - tier.from).max(0.0) * tier.rate)
+            .sum::<f64>()
+            + usage.min(first.from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{Local, TimeDelta};
+
+    use super::*;
+
+    fn booking(booked_minutes: i64, runtime_minutes: u64, energy: Option<(f64, f64)>) -> Booking {
+        Booking {
+            creation_datetime: Local::now() - TimeDelta::minutes(booked_minutes),
+            runtime_accumulator: Duration::from_secs(runtime_minutes * 60),
+            energy_at_booking: energy.map(|(start, _end)| start),
+            energy_latest: energy.map(|(_start, end)| end),
+            ..Booking::new("alice".into(), None)
+        }
+    }
+
+    fn rule(basis: BillingBasis) -> BillingRule {
+        BillingRule { basis, ..BillingRule::default() }
+    }
+
+    #[test]
+    fn bills_the_basis_in_rounded_up_pieces() {
+        let booking = booking(60, 25, Some((100.0, 102.5)));
+
+        assert_eq!(rule(BillingBasis::Runtime).quantity("Laser", &booking), 25);
+        assert_eq!(BillingRule { divider: 10.0, ..rule(BillingBasis::Runtime) }.quantity("Laser", &booking), 3);
+        assert_eq!(BillingRule { divider: 45.0, ..rule(BillingBasis::BookedTime) }.quantity("Laser", &booking), 2);
+        assert_eq!(rule(BillingBasis::Energy).quantity("Laser", &booking), 3);
+        assert_eq!(rule(BillingBasis::Flat).quantity("Laser", &booking), 0);
+    }
+
+    #[test]
+    fn energy_without_readings_bills_nothing() {
+        assert_eq!(rule(BillingBasis::Energy).quantity("Laser", &booking(60, 25, None)), 0);
+    }
+
+    #[test]
+    fn runtime_or_booked_takes_the_greater() {
+        let runtime_or_half = BillingRule { divider: 5.0, ..rule(BillingBasis::RuntimeOrBooked(0.5)) };
+
+        assert_eq!(runtime_or_half.quantity("Laser", &booking(60, 40, None)), 8);
+        assert_eq!(runtime_or_half.quantity("Laser", &booking(62, 10, None)), 7);
+    }
+
+    #[test]
+    fn minimum_and_base_fee() {
+        let rule = BillingRule { minimum: 5, base_fee: 2, ..rule(BillingBasis::Runtime) };
+
+        assert_eq!(rule.quantity("Laser", &booking(60, 3, None)), 7);
+        assert_eq!(rule.quantity("Laser", &booking(60, 10, None)), 12);
+        assert_eq!(BillingRule { base_fee: 2, ..self::rule(BillingBasis::Flat) }.quantity("Laser", &booking(60, 10, None)), 2);
+    }
+
+    #[test]
+    fn weighs_usage_by_tier() {
+        let first_15_free = BillingRule {
+            tiers: vec![Tier { from: 0.0, rate: 0.0 }, Tier { from: 15.0, rate: 1.0 }],
+            ..rule(BillingBasis::Runtime)
+        };
+        let cheaper_after_an_hour = BillingRule {
+            tiers: vec![Tier { from: 60.0, rate: 0.5 }],
+            ..rule(BillingBasis::Runtime)
+        };
+
+        assert!((first_15_free.weighted(10.0) - 0.0).abs() < f64::EPSILON);
+        assert!((first_15_free.weighted(40.0) - 25.0).abs() < f64::EPSILON);
+        assert!((cheaper_after_an_hour.weighted(30.0) - 30.0).abs() < f64::EPSILON);
+        assert!((cheaper_after_an_hour.weighted(100.0) - 80.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn validation() {
+        assert!(BillingRule::default().validate().is_ok());
+        assert!(BillingRule { divider: 0.0, ..BillingRule::default() }.validate().is_err());
+        assert!(rule(BillingBasis::RuntimeOrBooked(1.5)).validate().is_err());
+        assert!(BillingRule { tiers: vec![Tier { from: 10.0, rate: 1.0 }, Tier { from: 5.0, rate: 1.0 }], ..BillingRule::default() }.validate().is_err());
+    }
+}
@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+use tap::Pipe;
+
+///a log and how it gets rotated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFile {
+    pub path: String,
+    pub rotation: Rotation,
+    ///gzip rotated files
+    pub compress: bool,
+    ///rotated files older than this get deleted
+    pub retention: Option<Duration>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Never,
+    Daily,
+    Weekly,
+    Monthly,
+    ///bytes
+    Size(u64)
+}
+
+impl Rotation {
+    ///"never", "daily", "weekly", "monthly" or a size like "50MB"
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let rotation = match text.trim().to_ascii_lowercase().as_str() {
+            "never" => Self::Never,
+            "daily" => Self::Daily,
+            "weekly" => Self::Weekly,
+            "monthly" => Self::Monthly,
+            size => {
+                let split = size
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(size.len());
+                let (number, unit) = size.split_at(split);
+
+                let factor = match unit.trim() {
+                    "" | "b" => 1,
+                    "kb" => 1 << 10,
+                    "mb" => 1 << 20,
+                    "gb" => 1 << 30,
+                    _ => return Err(format!("`{text}` is neither never, daily, weekly, monthly nor a size like 50MB"))
+                };
+
+                number
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|number| *number > 0)
+                    .ok_or_else(|| format!("`{text}` is not a positive size"))?
+                    .pipe(|number| Self::Size(number * factor))
+            }
+        };
+
+        Ok(rotation)
+    }
+}
@@ -0,0 +1,18 @@
+pub fn default_on() -> String {
+    "ON".into()
+}
+
+pub fn default_off() -> String {
+    "OFF".into()
+}
+
+///whether the payload means on or off, `None` if it is neither. shared by sensors and slaves
+pub fn on_off(state: &str, on: &str, off: &str) -> Option<bool> {
+    if state == on {
+        Some(true)
+    } else if state == off {
+        Some(false)
+    } else {
+        None
+    }
+}
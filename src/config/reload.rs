@@ -0,0 +1,60 @@
+use std::fs;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::select;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+use tokio::time::sleep;
+use tracing::{error, info};
+
+use super::{SharedConfig, SpacerConfig};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+///reloads the config whenever one of its files changes or SIGHUP is received.
+///a config that fails validation is rejected and the previous one stays active.
+///connection settings (MQTT, web server) only take effect after a restart.
+pub async fn watch(config: SharedConfig, reloaded: Arc<Notify>) -> ! {
+    let mut hangup = signal(SignalKind::hangup()).expect("failed to register SIGHUP handler");
+    let mut last_modified = modification_times(&config.load());
+
+    loop {
+        select! {
+            _ = hangup.recv() => info!("SIGHUP received - reloading config"),
+            () = sleep(POLL_INTERVAL) => {
+                if modification_times(&config.load()) == last_modified {
+                    continue;
+                }
+                info!("config files changed - reloading config");
+            }
+        }
+
+        if reload(&config) {
+            reloaded.notify_one();
+        }
+        last_modified = modification_times(&config.load());
+    }
+}
+
+fn reload(config: &SharedConfig) -> bool {
+    let (new_config, report) = SpacerConfig::load();
+    report.print();
+
+    let Some(new_config) = new_config else {
+        error!("reload rejected - keeping previous config");
+        return false;
+    };
+
+    config.store(Arc::new(new_config));
+    info!("config reloaded");
+    true
+}
+
+fn modification_times(config: &SpacerConfig) -> Vec<Option<SystemTime>> {
+    config
+        .source_files
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+        .collect()
+}
@@ -0,0 +1,449 @@
+use std::time::Duration;
+use std::collections::HashMap;
+
+use json::JsonValue;
+use serde::Deserialize;
+use tap::Pipe;
+
+use super::payload::{self, default_off, default_on};
+
+///placeholder in topic templates, only allowed as a whole topic level
+const MACHINE_PLACEHOLDER: &str = "{machine}";
+
+///how spacermake learns about the machines, see sensors.toml
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sensors {
+    #[serde(default)]
+    pub power: SensorTable<PowerSensor>,
+    #[serde(default)]
+    pub energy: SensorTable<EnergyMeter>
+}
+
+///a sensor per machine, falling back to `default` for machines that aren't listed
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, bound(deserialize = "S: Sensor + Deserialize<'de>"))]
+pub struct SensorTable<S> {
+    #[serde(default = "S::fallback")]
+    pub default: Option<S>,
+    #[serde(default)]
+    pub machines: HashMap<String, S>
+}
+
+impl<S: Sensor> Default for SensorTable<S> {
+    fn default() -> Self {
+        Self {
+            default: S::fallback(),
+            machines: HashMap::new()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "driver", rename_all = "snake_case", deny_unknown_fields)]
+pub enum PowerSensor {
+    ///Tasmota's `PowerHigh` margin, which flips once the threshold configured on the device is crossed
+    TasmotaMargins {
+        #[serde(default = "default_margins_topic")]
+        topic: String
+    },
+    ///a JSON value compared against the payloads for on and off, e.g. `/POWER`
+    JsonPointer {
+        topic: String,
+        pointer: String,
+        #[serde(default = "default_on")]
+        on: String,
+        #[serde(default = "default_off")]
+        off: String
+    },
+    ///a numeric JSON value in watts, by default Tasmota's raw ENERGY telemetry.
+    ///counts as on above `threshold` and as off again below `off_threshold`,
+    ///but only once the reading stayed on that side for `min_dwell`
+    Watts {
+        #[serde(default = "default_energy_topic")]
+        topic: String,
+        #[serde(default = "default_power_pointer")]
+        pointer: String,
+        threshold: f64,
+        off_threshold: Option<f64>,
+        #[serde(default, with = "humantime_serde")]
+        min_dwell: Duration
+    },
+    ///the whole payload is the state
+    Plain {
+        topic: String,
+        #[serde(default = "default_on")]
+        on: String,
+        #[serde(default = "default_off")]
+        off: String
+    }
+}
+
+///a cumulative meter in kWh, by default Tasmota's ENERGY telemetry
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EnergyMeter {
+    #[serde(default = "default_energy_topic")]
+    pub topic: String,
+    #[serde(default = "default_energy_pointer")]
+    pub pointer: String
+}
+
+impl EnergyMeter {
+    pub fn read(&self, payload: &str) -> Result<f64, &'static str> {
+        parse_json(payload)?
+            .pipe_ref(|json| pointer(json, &self.pointer).and_then(JsonValue::as_f64))
+            .ok_or("no numeric energy value at pointer")
+    }
+}
+
+impl Default for PowerSensor {
+    fn default() -> Self {
+        Self::TasmotaMargins { topic: default_margins_topic() }
+    }
+}
+
+fn default_margins_topic() -> String {
+    format!("tele/{MACHINE_PLACEHOLDER}/MARGINS")
+}
+
+fn default_energy_topic() -> String {
+    format!("tele/{MACHINE_PLACEHOLDER}/SENSOR")
+}
+
+fn default_power_pointer() -> String {
+    "/ENERGY/Power".into()
+}
+
+fn default_energy_pointer() -> String {
+    "/ENERGY/Total".into()
+}
+
+pub trait Sensor: Sized {
+    ///topic template, may contain the machine placeholder
+    fn topic(&self) -> &str;
+
+    ///the default for machines that aren't listed, unless sensors.toml has one
+    fn fallback() -> Option<Self>;
+}
+
+impl Sensor for PowerSensor {
+    fn topic(&self) -> &str {
+        match self {
+            Self::TasmotaMargins { topic }
+            | Self::JsonPointer { topic, .. }
+            | Self::Watts { topic, .. }
+            | Self::Plain { topic, .. } => topic
+        }
+    }
+
+    ///what spacermake always did
+    fn fallback() -> Option<Self> {
+        Some(Self::default())
+    }
+}
+
+impl Sensor for EnergyMeter {
+    fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    ///every Tasmota device publishes a SENSOR topic, so this has to be opted into
+    fn fallback() -> Option<Self> {
+        None
+    }
+}
+
+impl PowerSensor {
+    ///readings that need debouncing before they count, see `Detector`
+    pub const fn min_dwell(&self) -> Option<Duration> {
+        match self {
+            Self::Watts { min_dwell, .. } => Some(*min_dwell),
+            _ => None
+        }
+    }
+
+    ///whether the payload says the machine is running, `None` if it's within the hysteresis
+    pub fn read(&self, payload: &str) -> Result<Option<bool>, &'static str> {
+        match self {
+            Self::TasmotaMargins { .. } => {
+                let power_high = parse_json(payload)?
+                    .pipe_ref(|json| pointer(json, "/MARGINS/PowerHigh"))
+                    .ok_or("no MARGINS.PowerHigh data present in payload")?
+                    .as_str()
+                    .ok_or("PowerHigh state was not a string")?
+                    .to_owned();
+
+                on_off(&power_high, "ON", "OFF").map(Some)
+            },
+            Self::JsonPointer { pointer: path, on, off, .. } => {
+                let json = parse_json(payload)?;
+                let value = pointer(&json, path).ok_or("pointer doesn't match payload")?;
+                let state = value.as_str().map_or_else(|| value.dump(), str::to_owned); // so booleans compare as "true"/"false"
+                on_off(&state, on, off).map(Some)
+            },
+            Self::Watts { pointer: path, threshold, off_threshold, .. } => {
+                let watts = parse_json(payload)?
+                    .pipe_ref(|json| pointer(json, path).and_then(JsonValue::as_f64))
+                    .ok_or("no numeric power value at pointer")?;
+
+                if watts > *threshold {
+                    Ok(Some(true))
+                } else if watts < off_threshold.unwrap_or(*threshold) {
+                    Ok(Some(false))
+                } else {
+                    Ok(None)
+                }
+            },
+            Self::Plain { on, off, .. } => on_off(payload.trim(), on, off).map(Some)
+        }
+    }
+}
+
+impl<S: Sensor> SensorTable<S> {
+    ///the problems with the topics and machine names of this table, keyed like in sensors.toml
+    pub fn problems(&self, table: &str) -> Vec<(String, String)> {
+        let templates = self
+            .machines
+            .iter()
+            .map(|(machine, sensor)| (format!("{table}.machines.{machine}"), sensor.topic()))
+            .chain(self.default.iter().map(|default| (format!("{table}.default"), default.topic())))
+            .filter_map(|(key, template)| check_template(template).err().map(|problem| (key, problem)));
+
+        self.machines
+            .keys()
+            .filter_map(|machine| check_machine_name(machine).err().map(|problem| (format!("{table}.machines.{machine}"), problem)))
+            .chain(templates)
+            .collect()
+    }
+
+    ///the machine whose sensor publishes on this topic. explicitly listed machines take precedence over the default
+    pub fn find(&self, topic: &str) -> Option<(String, &S)> {
+        self.machines
+            .iter()
+            .find(|(machine, sensor)| fill_template(sensor.topic(), machine) == topic)
+            .map(|(machine, sensor)| (machine.clone(), sensor))
+            .or_else(|| {
+                let default = self.default.as_ref()?;
+                match_template(default.topic(), topic)
+                    .filter(|machine| !self.machines.contains_key(machine))
+                    .map(|machine| (machine, default))
+            })
+    }
+
+    ///MQTT topic filters covering every sensor
+    pub fn subscriptions(&self) -> Vec<String> {
+        let mut topics = self
+            .machines
+            .iter()
+            .map(|(machine, sensor)| fill_template(sensor.topic(), machine))
+            .chain(self.default.iter().map(|default| fill_template(default.topic(), "+")))
+            .collect::<Vec<_>>();
+
+        topics.sort();
+        topics.dedup();
+        topics
+    }
+}
+
+///the placeholder gets replaced by `+` to subscribe and matched against a whole level, so it can't be part of one
+fn check_template(template: &str) -> Result<(), String> {
+    let placeholders = template.matches(MACHINE_PLACEHOLDER).count();
+    let whole_levels = template.split('/').filter(|level| *level == MACHINE_PLACEHOLDER).count();
+
+    if placeholders != whole_levels {
+        return Err(format!("`{MACHINE_PLACEHOLDER}` must be a whole topic level in `{template}`"));
+    }
+
+    if template.split('/').any(|level| level.contains(['+', '#'])) {
+        return Err(format!("wildcards aren't allowed in `{template}`"));
+    }
+
+    Ok(())
+}
+
+///machine names end up in topics, where these would be wildcards or extra levels
+pub fn check_machine_name(machine: &str) -> Result<(), String> {
+    if machine.contains(['+', '#', '/']) {
+        return Err(format!("machine name `{machine}` must not contain `+`, `#` or `/`"));
+    }
+
+    Ok(())
+}
+
+fn fill_template(template: &str, machine: &str) -> String {
+    template.replace(MACHINE_PLACEHOLDER, machine)
+}
+
+///extracts the machine name if the topic matches the template
+fn match_template(template: &str, topic: &str) -> Option<String> {
+    let template_levels = template.split('/').collect::<Vec<_>>();
+    let topic_levels = topic.split('/').collect::<Vec<_>>();
+
+    if template_levels.len() != topic_levels.len() {
+        return None;
+    }
+
+    let mut machine = None;
+    for (expected, actual) in template_levels.into_iter().zip(topic_levels) {
+        if expected == MACHINE_PLACEHOLDER {
+            machine = Some(actual.to_owned());
+        } else if expected != actual {
+            return None;
+        }
+    }
+
+    machine
+}
+
+fn parse_json(payload: &str) -> Result<JsonValue, &'static str> {
+    json::parse(payload).map_err(|_| "payload is not a valid json string")
+}
+
+///RFC 6901 JSON pointer, e.g. `/ENERGY/Power` or `/switches/0/output`
+fn pointer<'json>(json: &'json JsonValue, path: &str) -> Option<&'json JsonValue> {
+    path.split('/')
+        .skip(1)
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .try_fold(json, |value, token| {
+            let next = match value {
+                JsonValue::Array(items) => items.get(token.parse::<usize>().ok()?)?,
+                _ => &value[token.as_str()]
+            };
+            (!next.is_null()).then_some(next)
+        })
+}
+
+fn on_off(state: &str, on: &str, off: &str) -> Result<bool, &'static str> {
+    payload::on_off(state, on, off).ok_or("unknown power state")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sensors(toml: &str) -> Sensors {
+        toml::from_str(toml).expect("sensors.toml")
+    }
+
+    #[test]
+    fn match_template_extracts_the_machine() {
+        let cases = [
+            ("tele/{machine}/MARGINS", "tele/Laser/MARGINS", Some("Laser")),
+            ("tele/{machine}/MARGINS", "tele/Laser/SENSOR", None),
+            ("tele/{machine}/MARGINS", "tele/Laser/MARGINS/extra", None),
+            ("tele/{machine}/MARGINS", "stat/Laser/MARGINS", None),
+            ("{machine}/power", "Saw/power", Some("Saw")),
+            ("shellies/plug/status", "shellies/plug/status", None)
+        ];
+
+        for (template, topic, machine) in cases {
+            assert_eq!(match_template(template, topic).as_deref(), machine, "{template} ~ {topic}");
+        }
+    }
+
+    #[test]
+    fn listed_machines_take_precedence_over_the_default() {
+        let sensors = sensors(r#"
+            [power.machines.Laser]
+            driver = "plain"
+            topic = "stat/{machine}/POWER"
+
+            [power.machines.Saw]
+            driver = "plain"
+            topic = "shellies/saw/relay"
+        "#);
+
+        let find = |topic| sensors.power.find(topic).map(|(machine, sensor)| (machine, sensor.clone()));
+
+        assert!(matches!(find("stat/Laser/POWER"), Some((machine, PowerSensor::Plain { .. })) if machine == "Laser"));
+        assert!(matches!(find("shellies/saw/relay"), Some((machine, PowerSensor::Plain { .. })) if machine == "Saw"));
+        assert!(matches!(find("tele/Drill/MARGINS"), Some((machine, PowerSensor::TasmotaMargins { .. })) if machine == "Drill"));
+        // a listed machine is never read through the default topic
+        assert_eq!(find("tele/Laser/MARGINS"), None);
+    }
+
+    #[test]
+    fn energy_meters_are_opt_in() {
+        assert!(sensors("").energy.find("tele/Laser/SENSOR").is_none());
+        assert_eq!(sensors("").energy.subscriptions(), Vec::<String>::new());
+        assert_eq!(sensors("[energy.default]").energy.subscriptions(), ["tele/+/SENSOR"]);
+    }
+
+    #[test]
+    fn subscriptions_cover_every_sensor_once() {
+        let sensors = sensors(r#"
+            [power.machines.Laser]
+            driver = "tasmota_margins"
+
+            [power.machines.Saw]
+            driver = "plain"
+            topic = "shellies/saw/relay"
+        "#);
+
+        assert_eq!(sensors.power.subscriptions(), ["shellies/saw/relay", "tele/+/MARGINS", "tele/Laser/MARGINS"]);
+    }
+
+    #[test]
+    fn placeholders_have_to_be_whole_levels() {
+        let cases = [
+            ("tele/{machine}/SENSOR", true),
+            ("{machine}", true),
+            ("shellies/plug/status", true),
+            ("tele/{machine}-plug/SENSOR", false),
+            ("tele/x{machine}/SENSOR", false),
+            ("tele/+/SENSOR", false),
+            ("tele/{machine}/#", false)
+        ];
+
+        for (template, valid) in cases {
+            assert_eq!(check_template(template).is_ok(), valid, "{template}");
+        }
+    }
+
+    #[test]
+    fn machine_names_cant_contain_topic_syntax() {
+        for (machine, valid) in [("Laser Cutter", true), ("Laser/2", false), ("Laser+", false), ("#1", false)] {
+            assert_eq!(check_machine_name(machine).is_ok(), valid, "{machine}");
+        }
+    }
+
+    #[test]
+    fn pointer_follows_rfc_6901() {
+        let json = json::parse(r#"{"ENERGY": {"Power": 42}, "a/b": 1, "m~n": 2, "switches": [{"output": true}, {"output": false}]}"#)
+            .expect("json");
+
+        let cases = [
+            ("/ENERGY/Power", Some("42")),
+            ("/a~1b", Some("1")),
+            ("/m~0n", Some("2")),
+            ("/switches/0/output", Some("true")),
+            ("/switches/1/output", Some("false")),
+            ("/switches/2/output", None),
+            ("/switches/x", None),
+            ("/ENERGY/Total", None)
+        ];
+
+        for (path, value) in cases {
+            assert_eq!(pointer(&json, path).map(JsonValue::dump).as_deref(), value, "{path}");
+        }
+    }
+
+    #[test]
+    fn watts_respect_the_hysteresis() {
+        let sensor = PowerSensor::Watts {
+            topic: default_energy_topic(),
+            pointer: default_power_pointer(),
+            threshold: 80.0,
+            off_threshold: Some(40.0),
+            min_dwell: Duration::ZERO
+        };
+
+        let read = |watts: f64| sensor.read(&format!(r#"{{"ENERGY": {{"Power": {watts}}}}}"#));
+
+        assert_eq!(read(100.0), Ok(Some(true)));
+        assert_eq!(read(60.0), Ok(None));
+        assert_eq!(read(20.0), Ok(Some(false)));
+        assert!(sensor.read("{}").is_err());
+    }
+}
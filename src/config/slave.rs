@@ -1,8 +1,97 @@
-#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Deserialize)]
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer};
+use tap::Pipe;
+
+use super::payload::{default_off, default_on, on_off};
+
+///what `needs_trailing_time = true` used to mean
+const LEGACY_TRAILING_TIME: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
 pub struct Slave {
     pub runs_continuously: bool,
-    pub needs_trailing_time: bool,
+    ///how long the slave keeps running after its master stopped, e.g. "30s" or "3m".
+    ///the boolean `needs_trailing_time` is still accepted and means 30 seconds
+    #[serde(default, alias = "needs_trailing_time", deserialize_with = "trailing_time")]
+    pub trailing_time: Option<Duration>,
     pub topic: String,
     pub payload_on: String,
     pub payload_off: String,
-}
\ No newline at end of file
+    ///where the device reports its actual relay state, e.g. Tasmota's `stat/<dev>/POWER` or `tele/<dev>/STATE`
+    pub state_topic: Option<String>,
+    #[serde(default = "default_on")]
+    pub state_on: String,
+    #[serde(default = "default_off")]
+    pub state_off: String,
+}
+
+impl Slave {
+    ///accepts both plain `stat/<dev>/POWER` payloads and JSON `tele/<dev>/STATE` payloads
+    pub fn parse_state(&self, payload: &str) -> Result<bool, &'static str> {
+        let parsed = json::parse(payload).ok();
+        let state = parsed
+            .as_ref()
+            .and_then(|json| json["POWER"].as_str())
+            .unwrap_or(payload)
+            .trim();
+
+        on_off(state, &self.state_on, &self.state_off).ok_or("unknown relay state")
+    }
+}
+
+fn trailing_time<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Flag(bool),
+        Human(String)
+    }
+
+    let trailing_time = match Raw::deserialize(deserializer)? {
+        Raw::Flag(needs_trailing_time) => needs_trailing_time.then_some(LEGACY_TRAILING_TIME),
+        Raw::Human(text) => humantime::parse_duration(&text)
+            .map_err(serde::de::Error::custom)?
+            .pipe(Some)
+    };
+
+    Ok(trailing_time.filter(|duration| !duration.is_zero()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trailing_time_of(value: &str) -> Result<Option<Duration>, toml::de::Error> {
+        let toml = format!(r#"
+            runs_continuously = false
+            topic = "cmnd/dust/POWER"
+            payload_on = "ON"
+            payload_off = "OFF"
+            {value}
+        "#);
+
+        toml::from_str::<Slave>(&toml).map(|slave| slave.trailing_time)
+    }
+
+    #[test]
+    fn trailing_time_accepts_the_legacy_flag_and_durations() {
+        let cases = [
+            ("needs_trailing_time = true", Some(LEGACY_TRAILING_TIME)),
+            ("needs_trailing_time = false", None),
+            (r#"trailing_time = "3m""#, Some(Duration::from_secs(180))),
+            (r#"trailing_time = "45s""#, Some(Duration::from_secs(45))),
+            (r#"trailing_time = "0s""#, None),
+            ("", None)
+        ];
+
+        for (value, trailing_time) in cases {
+            assert_eq!(trailing_time_of(value).expect("slave"), trailing_time, "{value}");
+        }
+    }
+
+    #[test]
+    fn trailing_time_rejects_nonsense() {
+        assert!(trailing_time_of(r#"trailing_time = "soon""#).is_err());
+    }
+}
@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+///pricing for members of a group, see user_groups.toml
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserGroup {
+    ///applied to the billed quantity, e.g. 0.5 for half price or 0 for free
+    #[serde(default = "default_multiplier")]
+    pub multiplier: f64,
+    #[serde(default)]
+    pub machines: HashMap<String, GroupPricing>
+}
+
+///per machine overrides of the group's pricing
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupPricing {
+    ///a different article, for price levels the accounting software knows about
+    pub artikel_id: Option<i32>,
+    pub multiplier: Option<f64>
+}
+
+fn default_multiplier() -> f64 {
+    1.0
+}
+
+impl UserGroup {
+    pub fn multiplier(&self, machine: &str) -> f64 {
+        self.machines
+            .get(machine)
+            .and_then(|pricing| pricing.multiplier)
+            .unwrap_or(self.multiplier)
+    }
+
+    pub fn artikel_id(&self, machine: &str) -> Option<i32> {
+        self.machines
+            .get(machine)
+            .and_then(|pricing| pricing.artikel_id)
+    }
+
+    ///the problem with this group, if any
+    pub fn validate(&self) -> Result<(), String> {
+        let multipliers = self.machines
+            .values()
+            .filter_map(|pricing| pricing.multiplier)
+            .chain([self.multiplier]);
+
+        for multiplier in multipliers {
+            if multiplier < 0.0 {
+                return Err(format!("multiplier must not be negative, got {multiplier}"));
+            }
+        }
+
+        Ok(())
+    }
+}
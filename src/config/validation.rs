@@ -0,0 +1,73 @@
+use std::fmt::{self, Display};
+
+use tracing::{error, warn};
+
+#[derive(Debug)]
+pub struct Problem {
+    pub file: String,
+    pub location: Option<String>,
+    pub cause: String
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file)?;
+        if let Some(location) = &self.location {
+            write!(f, " @ {location}")?;
+        }
+        write!(f, " ~~ {}", self.cause)
+    }
+}
+
+///every problem found while loading the configuration, so they can be fixed in one go
+#[derive(Debug, Default)]
+pub struct Report {
+    pub errors: Vec<Problem>,
+    pub warnings: Vec<Problem>
+}
+
+impl Report {
+    pub fn error(&mut self, file: &str, location: Option<String>, cause: impl Display) {
+        self.errors.push(problem(file, location, cause));
+    }
+
+    pub fn warning(&mut self, file: &str, location: Option<String>, cause: impl Display) {
+        self.warnings.push(problem(file, location, cause));
+    }
+
+    pub const fn has_errors(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    pub fn print(&self) {
+        for warning in &self.warnings {
+            warn!("{warning}");
+        }
+        for error in &self.errors {
+            error!("{error}");
+        }
+        if self.has_errors() {
+            error!("configuration rejected ({} errors, {} warnings)", self.errors.len(), self.warnings.len());
+        }
+    }
+}
+
+fn problem(file: &str, location: Option<String>, cause: impl Display) -> Problem {
+    Problem {
+        file: file.to_owned(),
+        location,
+        cause: cause.to_string()
+    }
+}
+
+///1-based line number of a byte offset
+pub fn line_of(content: &str, offset: usize) -> String {
+    let line = content
+        .get(..offset)
+        .unwrap_or(content)
+        .matches('\n')
+        .count()
+        + 1;
+
+    format!("line {line}")
+}
@@ -0,0 +1,191 @@
+use std::fs;
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Context};
+use chrono::{Datelike, Local, Months, NaiveDate};
+use csv::Writer;
+use maud::{html, Markup, DOCTYPE};
+use tap::Pipe;
+use tracing::{debug, info};
+
+use crate::config::SpacerConfig;
+use crate::utils::logs::billing::export::LineItem;
+use crate::utils::logs::rotation::{log_files, open_log};
+
+///a member's billed quantities for the period
+#[derive(Debug, Default)]
+struct Statement {
+    user: String,
+    group: Option<String>,
+    ///article id -> (machine, quantity)
+    positions: BTreeMap<String, (String, i32)>
+}
+
+///`spacermake invoice [YYYY-MM]`, sums up the billing log per member and per machine.
+///defaults to the previous month
+pub fn run(period: Option<&str>, config: &SpacerConfig) -> anyhow::Result<()> {
+    let month = match period {
+        Some(period) => NaiveDate::parse_from_str(&format!("{period}-01"), "%Y-%m-%d")
+            .map_err(|_| anyhow!("invalid period `{period}`, expected YYYY-MM"))?,
+        None => Local::now()
+            .date_naive()
+            .with_day(1)
+            .and_then(|this_month| this_month.checked_sub_months(Months::new(1)))
+            .ok_or_else(|| anyhow!("can't determine previous month"))?
+    };
+    let period = month.format("%Y-%m").to_string();
+
+    let mut items = Vec::new();
+    for path in log_files(&config.billing_log.path).with_context(|| format!("failed to list {}", config.billing_log.path))? {
+        open_log(&path)
+            .with_context(|| format!("failed to open {}", path.display()))?
+            .pipe(|file| config.billing_exporter.import(file))
+            .pipe(|imported| items.extend(imported));
+    }
+
+    let mut statements = BTreeMap::<String, Statement>::new();
+    let mut machine_totals = BTreeMap::<String, i32>::new();
+
+    for item in items {
+        if item.date.year() != month.year() || item.date.month() != month.month() {
+            continue;
+        }
+
+        let Some((user, machine)) = resolve(&item, config) else {
+            debug!("skipping excluded line item {item:?}");
+            continue;
+        };
+
+        let statement = statements
+            .entry(item.user_id.clone())
+            .or_insert_with(|| Statement { user, ..Statement::default() });
+
+        if item.group.is_some() {
+            statement.group.clone_from(&item.group); // the most recent one
+        }
+
+        statement
+            .positions
+            .entry(item.article_id.clone())
+            .or_insert_with(|| (machine.clone(), 0))
+            .1 += item.quantity;
+
+        *machine_totals.entry(machine).or_default() += item.quantity;
+    }
+
+    write_statements(&format!("invoice_{period}.csv"), &statements)?;
+    write_machine_totals(&format!("invoice_{period}_machines.csv"), &machine_totals)?;
+    fs::write(format!("invoice_{period}.html"), page(&period, &statements, &machine_totals).into_string())?;
+
+    info!("{} statements for {period}", statements.len());
+    for (machine, total) in &machine_totals {
+        info!("  {machine}: {total}");
+    }
+
+    Ok(())
+}
+
+///user and machine names, `None` if either is excluded from billing by now
+fn resolve(item: &LineItem, config: &SpacerConfig) -> Option<(String, String)> {
+    let (user, user_data) = config
+        .data_user
+        .iter()
+        .find(|(name, user_data)| **name == item.user || user_data.id.is_some_and(|id| id.to_string() == item.user_id))
+        .map_or((item.user.clone(), None), |(name, user_data)| (name.clone(), Some(user_data)));
+
+    if user_data.is_some_and(|user_data| !user_data.to_be_used) {
+        return None;
+    }
+
+    let mut group_articles = config
+        .user_groups
+        .values()
+        .flat_map(|group| &group.machines)
+        .filter(|(_machine, pricing)| pricing.artikel_id.is_some_and(|id| id.to_string() == item.article_id))
+        .map(|(machine, _pricing)| machine);
+
+    let machine = config
+        .data_machines
+        .iter()
+        .find(|(name, machine_data)| **name == item.machine || machine_data.id.is_some_and(|id| id.to_string() == item.article_id))
+        .map(|(name, _machine_data)| name)
+        .or_else(|| group_articles.next())
+        .cloned()
+        .unwrap_or_else(|| item.machine.clone());
+
+    if config.data_machines.get(&machine).is_some_and(|machine_data| !machine_data.to_be_used) {
+        return None;
+    }
+
+    Some((user, machine))
+}
+
+fn write_statements(path: &str, statements: &BTreeMap<String, Statement>) -> anyhow::Result<()> {
+    let mut writer = Writer::from_path(path)?;
+    writer.write_record(["user_id", "user", "group", "article_id", "machine", "quantity"])?;
+
+    for (user_id, statement) in statements {
+        for (article_id, (machine, quantity)) in &statement.positions {
+            writer.write_record([
+                user_id,
+                &statement.user,
+                statement.group.as_deref().unwrap_or_default(),
+                article_id,
+                machine,
+                &quantity.to_string()
+            ])?;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_machine_totals(path: &str, machine_totals: &BTreeMap<String, i32>) -> anyhow::Result<()> {
+    let mut writer = Writer::from_path(path)?;
+    writer.write_record(["machine", "quantity"])?;
+
+    for (machine, total) in machine_totals {
+        writer.write_record([machine, &total.to_string()])?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn page(period: &str, statements: &BTreeMap<String, Statement>, machine_totals: &BTreeMap<String, i32>) -> Markup {
+    html! {
+        (DOCTYPE)
+        meta charset="utf-8";
+        title { "Abrechnung " (period) }
+        style { "section { break-after: page; } table { border-collapse: collapse; } td, th { border: 1px solid; padding: 4px 8px; }" }
+
+        @for (user_id, statement) in statements {
+            section {
+                h2 { (statement.user) " (" (user_id) ")" }
+                p {
+                    "Zeitraum " (period)
+                    @if let Some(group) = &statement.group {
+                        ", Gruppe " (group)
+                    }
+                }
+                table {
+                    tr { th { "Artikel" } th { "Maschine" } th { "Anzahl" } }
+                    @for (article_id, (machine, quantity)) in &statement.positions {
+                        tr { td { (article_id) } td { (machine) } td { (quantity) } }
+                    }
+                }
+            }
+        }
+
+        section {
+            h2 { "Summe je Maschine " (period) }
+            table {
+                tr { th { "Maschine" } th { "Anzahl" } }
+                @for (machine, total) in machine_totals {
+                    tr { td { (machine) } td { (total) } }
+                }
+            }
+        }
+    }
+}
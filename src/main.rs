@@ -1,50 +1,115 @@
+use std::fs;
+use std::io::{self, IsTerminal};
 use std::sync::Arc;
 use std::time::Duration;
 
-use colour::{dark_grey_ln, magenta_ln};
-use futures::future::join3;
-use rumqttc::{AsyncClient, EventLoop, MqttOptions, QoS};
-use state::{Announcer, Listener, State};
+use rumqttc::{AsyncClient, EventLoop, MqttOptions, Transport};
+use state::{Announcer, Listener, State, Web};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info};
+use tracing_subscriber::EnvFilter;
 
-use self::config::SpacerConfig;
+use arc_swap::ArcSwap;
+
+use self::config::{MqttTls, SpacerConfig};
 
 pub mod config;
+mod invoice;
 mod state;
 mod utils;
 mod web;
 mod schema;
 
-pub const BOOKING_TOPIC: &str = "fabaccess/log";
-
 #[tokio::main]
 async fn main() {
-	magenta_ln!("===== spacermake =====");
+	init_tracing();
+	info!("===== spacermake =====");
 
-	let my_config = Arc::new(SpacerConfig::load());
-	dark_grey_ln!("{my_config:#?}");
+	let (my_config, report) = SpacerConfig::load();
+	report.print();
+	let Some(my_config) = my_config else {
+		std::process::exit(1);
+	};
+	debug!("{my_config:#?}");
+
+	let args = std::env::args().skip(1).collect::<Vec<_>>();
+	if let Some(command) = args.first() {
+		let result = match command.as_str() {
+			"invoice" => invoice::run(args.get(1).map(String::as_str), &my_config),
+			_ => Err(anyhow::anyhow!("unknown command `{command}`, expected `invoice [YYYY-MM]`"))
+		};
+		if let Err(error) = result {
+			error!("{error:#}");
+			std::process::exit(1);
+		}
+		return;
+	}
 
-	let (client, event_loop) = create_client(&my_config).await;
-	magenta_ln!("start");
+	let (client, event_loop) = create_client(&my_config);
+	let my_config = Arc::new(ArcSwap::from_pointee(my_config));
+	info!("start");
 	let listener = State::new(Listener, client, Arc::clone(&my_config));
+	listener.restore().await;
 	let announcer = listener.duplicate_as(Announcer);
+	let recovery = listener.duplicate_as(Listener);
+	let web = listener.duplicate_as(Web);
+	let shutdown = CancellationToken::new();
+
+	tokio::join!(
+		shutdown_on_signal(shutdown.clone()),
+		shutdown.run_until_cancelled(config::reload::watch(my_config, Arc::clone(&listener.config_reloaded))),
+		web::start(web, shutdown.clone()),
+		announcer.run(shutdown.clone()),
+		listener.run(event_loop, shutdown.clone()),
+		shutdown.run_until_cancelled(recovery.reconcile())
+	);
+	info!("stopped");
+}
+
+async fn shutdown_on_signal(shutdown: CancellationToken) {
+	let mut terminate = signal(SignalKind::terminate()).expect("failed to register SIGTERM handler");
+	let mut interrupt = signal(SignalKind::interrupt()).expect("failed to register SIGINT handler");
+
+	tokio::select! {
+		_ = terminate.recv() => info!("received SIGTERM"),
+		_ = interrupt.recv() => info!("received SIGINT")
+	}
+	shutdown.cancel();
+}
 
-	join3(
-		web::start(my_config),
-		announcer.run(),
-		listener.run(event_loop)
-	).await;
+///`RUST_LOG` sets the levels, e.g. `RUST_LOG=spacermake=debug`. `SPACERMAKE_LOG_FORMAT=json` for journald/Loki
+fn init_tracing() {
+	let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+	let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+
+	if std::env::var("SPACERMAKE_LOG_FORMAT").is_ok_and(|format| format == "json") {
+		subscriber.json().init();
+	} else {
+		subscriber.with_ansi(io::stdout().is_terminal()).init();
+	}
 }
 
-async fn create_client(my_config: &SpacerConfig) -> (AsyncClient, EventLoop) {
-	let mut mqttoptions = MqttOptions::new("spacermake", &my_config.mqtt_host, 1883);
+fn create_client(my_config: &SpacerConfig) -> (AsyncClient, EventLoop) {
+	let mut mqttoptions = MqttOptions::new(&my_config.mqtt_client_id, &my_config.mqtt_host, my_config.mqtt_port);
 	mqttoptions.set_keep_alive(Duration::from_secs(5));
 	if let (Some(username), Some(password)) = (&my_config.mqtt_username, &my_config.mqtt_password) {
 		mqttoptions.set_credentials(username, password);
 	}
+	if let Some(tls) = &my_config.mqtt_tls {
+		mqttoptions.set_transport(tls_transport(tls).expect("failed to read MQTT TLS files"));
+	}
+
+	AsyncClient::new(mqttoptions, 10) // subscriptions happen once connected, see `State<Listener>::run`
+}
 
-	let (client, event_loop) = AsyncClient::new(mqttoptions, 10);
-	client.subscribe("tele/+/MARGINS", QoS::AtMostOnce).await.expect("failed to subscribe");
-	client.subscribe(BOOKING_TOPIC,    QoS::AtMostOnce).await.expect("failed to subscribe");
+fn tls_transport(tls: &MqttTls) -> io::Result<Transport> {
+	let ca = fs::read(&tls.ca)?;
+	let client_auth = tls
+		.client_auth
+		.as_ref()
+		.map(|(cert, key)| io::Result::Ok((fs::read(cert)?, fs::read(key)?)))
+		.transpose()?;
 
-	(client, event_loop)
-}
\ No newline at end of file
+	Ok(Transport::tls(ca, client_auth, None))
+}
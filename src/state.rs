@@ -1,38 +1,62 @@
-use std::time::Instant;
 use std::sync::Arc;
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
 
-use colour::dark_grey_ln;
-use rumqttc::{AsyncClient, QoS};
-use tokio::sync::RwLock;
+use rumqttc::AsyncClient;
+use tokio::sync::{Mutex, Notify, RwLock};
+use tracing::{debug, error};
 
-use crate::config::SpacerConfig;
+use crate::config::{SharedConfig, SpacerConfig};
+use crate::config::slave::Slave;
 use crate::utils::booking::Booking;
+use crate::utils::detector::Detector;
+use crate::utils::metrics::SLAVE_POWERED;
+use crate::utils::relay::Relay;
+use crate::utils::schedule::Schedule;
+use crate::utils::status::Status;
 
 mod announcer;
 mod listener;
+mod persistence;
+mod shutdown;
 
 //markers
 pub struct Listener;
 pub struct Announcer;
+pub struct Web;
 
 pub struct State<Kind> {
     #[expect(dead_code, reason = "like PhantomData")]
     pub kind: Kind,
-    pub config: Arc<SpacerConfig>,
+    pub config: SharedConfig,
     pub client: Arc<RwLock<AsyncClient>>,
     pub bookings: Arc<RwLock<HashMap<String, Booking>>>,
-    pub scheduled_shutdowns: Arc<RwLock<VecDeque<(Instant, String)>>>
+    pub scheduled_shutdowns: Arc<RwLock<Schedule>>,
+    pub schedule_changed: Arc<Notify>,
+    pub relays: Arc<RwLock<HashMap<String, Relay>>>,
+    pub detectors: Arc<RwLock<HashMap<String, Detector>>>,
+    ///latest energy meter reading per machine, in kWh
+    pub energy_meters: Arc<RwLock<HashMap<String, f64>>>,
+    pub status: Arc<RwLock<Status>>,
+    pub config_reloaded: Arc<Notify>,
+    ///the state file's content as last written, so unchanged state doesn't get written again
+    persisted: Arc<Mutex<String>>
 }
 
 impl<Kind> State<Kind> {
-    pub fn new(kind: Kind, client: AsyncClient, config: Arc<SpacerConfig>) -> Self {
+    pub fn new(kind: Kind, client: AsyncClient, config: SharedConfig) -> Self {
         Self {
             kind,
             config,
             client: Arc::new(RwLock::new(client)),
             bookings: Default::default(),
-            scheduled_shutdowns: Default::default()
+            scheduled_shutdowns: Default::default(),
+            schedule_changed: Default::default(),
+            relays: Default::default(),
+            detectors: Default::default(),
+            energy_meters: Default::default(),
+            status: Default::default(),
+            config_reloaded: Default::default(),
+            persisted: Default::default()
         }
     }
 
@@ -42,22 +66,59 @@ impl<Kind> State<Kind> {
             config: Arc::clone(&self.config),
             client: Arc::clone(&self.client),
             bookings: Arc::clone(&self.bookings),
-            scheduled_shutdowns: Arc::clone(&self.scheduled_shutdowns)
+            scheduled_shutdowns: Arc::clone(&self.scheduled_shutdowns),
+            schedule_changed: Arc::clone(&self.schedule_changed),
+            relays: Arc::clone(&self.relays),
+            detectors: Arc::clone(&self.detectors),
+            energy_meters: Arc::clone(&self.energy_meters),
+            status: Arc::clone(&self.status),
+            config_reloaded: Arc::clone(&self.config_reloaded),
+            persisted: Arc::clone(&self.persisted)
         }
     }
 
+    ///snapshot of the current config, stays consistent even if a reload happens meanwhile
+    pub fn config(&self) -> Arc<SpacerConfig> {
+        self.config.load_full()
+    }
+
     //probably doesn't belong here, dunno where else to put it
     async fn set_power_state(&self, machine: &str, new_state: bool) {
-        dark_grey_ln!("set power state - {machine} {new_state}");
-        let props = &self.config.slave_properties[machine];
+        debug!("set power state - {machine} {new_state}");
+        let config = self.config();
+        let Some(props) = config.slave_properties.get(machine) else {
+            error!("unknown slave {machine}");
+            return;
+        };
+
+        // slaves reporting their relay state update the metric once they confirm
+        if props.state_topic.is_some() {
+            self.relays
+                .write()
+                .await
+                .insert(machine.to_owned(), Relay::commanded(new_state));
+        } else {
+            SLAVE_POWERED
+                .with_label_values(&[machine])
+                .set(new_state.into());
+        }
+
+        self.publish_power_state(props, new_state).await;
+    }
+
+    async fn publish_power_state(&self, props: &Slave, new_state: bool) {
         let payload = if new_state { &props.payload_on } else { &props.payload_off };
 
-        dark_grey_ln!("publishing\n  topic: {}\n  payload: {:?}", props.topic, payload);
-        self.client
+        debug!(topic = props.topic, payload, "publishing");
+
+        // the queue only drains while connected. waiting on it would stall whoever holds state locks
+        let published = self.client
             .read()
             .await
-            .publish(&props.topic, QoS::AtMostOnce, false, payload.as_bytes())
-            .await
-            .expect("failed to publish");
+            .try_publish(&props.topic, self.config().mqtt_qos, false, payload.as_bytes());
+
+        if let Err(error) = published {
+            error!("failed to publish {payload} to {} ~~ {error}", props.topic);
+        }
     }
 }
\ No newline at end of file
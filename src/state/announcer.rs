@@ -1,52 +1,127 @@
+use std::future::pending;
 use std::time::{Duration, Instant};
 
-use colour::{blue_ln, red_ln};
-use futures::join;
+use chrono::Local;
 use futures::future::join_all;
-use rumqttc::QoS;
 use tap::Pipe;
+use tokio::select;
 use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info};
 
-use crate::utils::{create_display_time_string, minute_mark};
+use crate::utils::{create_display_time_string, minute_mark, time_until};
 use crate::{Announcer, State};
 
 impl State<Announcer> {
-    pub async fn run(self) -> ! {
+    ///returns on shutdown, leaving the remaining scheduled shutdowns to `State<Listener>::shut_down`
+    pub async fn run(self, shutdown: CancellationToken) {
+        select! {
+            never = self.run_runtime_displays() => never,
+            () = self.run_scheduled_shutdowns(&shutdown) => {},
+            never = self.run_relay_confirmations() => never
+        }
+    }
+
+    async fn run_relay_confirmations(&self) -> ! {
         loop {
-            join!(
-                self.update_all_runtime_displays(),
-                self.perform_scheduled_shutdowns()
-            );
+            self.confirm_relays().await;
+            sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    ///repeats commands that slaves didn't confirm in time
+    async fn confirm_relays(&self) {
+        let config = self.config();
+        let mut to_repeat = Vec::new();
 
+        for (slave, relay) in self.relays.write().await.iter_mut() {
+            if relay.is_confirmed() || relay.given_up || relay.commanded_at.elapsed() < config.slave_confirmation_timeout {
+                continue;
+            }
+
+            if relay.retries >= config.slave_max_retries {
+                error!("{slave} reports {:?} instead of {} - giving up after {} retries", relay.actual, relay.target, relay.retries);
+                relay.given_up = true;
+                continue;
+            }
+
+            relay.retries += 1;
+            relay.commanded_at = Instant::now();
+            error!("{slave} reports {:?} instead of {} - retrying ({}/{})", relay.actual, relay.target, relay.retries, config.slave_max_retries);
+            to_repeat.push((slave.clone(), relay.target));
+        }
+
+        for (slave, target) in to_repeat {
+            if let Some(props) = config.slave_properties.get(&slave) {
+                self.publish_power_state(props, target).await;
+            }
+        }
+    }
+
+    async fn run_runtime_displays(&self) -> ! {
+        loop {
+            self.status.write().await.announcer_tick = Some(Local::now());
+            self.update_all_runtime_displays().await;
             sleep(Duration::from_secs(1)).await;
         }
     }
 
+    ///sleeps until the earliest deadline, or until the schedule changes.
+    ///only stops between deadlines so a shutdown can't get lost halfway
+    async fn run_scheduled_shutdowns(&self, shutdown: &CancellationToken) {
+        loop {
+            let next_deadline = self.scheduled_shutdowns.write().await.next_deadline();
+
+            let deadline_reached = async {
+                match next_deadline {
+                    Some(deadline) => sleep(time_until(deadline)).await,
+                    None => pending().await
+                }
+            };
+
+            select! {
+                () = deadline_reached => self.perform_scheduled_shutdowns().await,
+                () = self.schedule_changed.notified() => {},
+                () = shutdown.cancelled() => return
+            }
+        }
+    }
+
+    ///skipped while the broker is down, the displays catch up on the next minute mark
     async fn update_all_runtime_displays(&self) {
-        self.bookings
+        if !self.status.read().await.mqtt_connected {
+            return;
+        }
+
+        let config = self.config();
+        let displays = self
+            .bookings
             .read()
             .await
             .iter()
+            .filter(|(_machine, booking)| booking.is_running() && minute_mark(booking.total_runtime()))
             .filter_map(|(machine, booking)| {
-                if !booking.is_running() || !minute_mark(booking.total_runtime()) {
-                    return None;
-                }
-
-                blue_ln!("updating display of {machine}");
+                debug!("updating display of {machine}");
 
-                let Some(id) = self.config.machine_ids.get(machine) else {
-                    red_ln!("error: no ID found for {machine}");
+                let Some(id) = config.machine_ids.get(machine) else {
+                    error!("no ID found for {machine}");
                     return None;
                 };
 
-                let future = self.update_runtime_display(id, booking.total_runtime());
-                Some(future)
+                Some((id.clone(), booking.total_runtime()))
             })
+            .collect::<Vec<_>>();
+
+        displays
+            .iter()
+            .map(|(id, runtime)| self.update_runtime_display(id, *runtime))
             .pipe(join_all)
             .await;
     }
 
+    ///never waits for the request queue, a display update isn't worth stalling the announcer
     async fn update_runtime_display(&self, machine_id: &str, runtime: Duration) {
+        let qos = self.config().mqtt_qos;
         let client = self.client.read().await;
 
         let messages = [
@@ -55,29 +130,27 @@ impl State<Announcer> {
         ];
 
         for (route, payload) in messages {
-            client
-                .publish(
-                    format!("fabreader/{machine_id}/display/{route}"),
-                    QoS::AtMostOnce,
-                    false,
-                    payload
-                )
-                .await
-                .expect("failed to publish display update");
+            let topic = format!("fabreader/{machine_id}/display/{route}");
+            if let Err(error) = client.try_publish(&topic, qos, false, payload) {
+                debug!("dropped display update for {topic} ~~ {error}");
+            }
         }
     }
 
     async fn perform_scheduled_shutdowns(&self) {
-        let now = Instant::now();
-        let mut schedule = self.scheduled_shutdowns.write().await;
-        while let Some((time, machine)) = schedule.front() {
-            if time > &now {
-                break;
-            }
-            blue_ln!("performing scheduled shutdown of {machine}");
+        let due = self
+            .scheduled_shutdowns
+            .write()
+            .await
+            .pop_due(Local::now());
 
+        for machine in &due {
+            info!("performing scheduled shutdown of {machine}");
             self.set_power_state(machine, false).await;
-            schedule.pop_front();
+        }
+
+        if !due.is_empty() {
+            self.persist().await;
         }
     }
 }
\ No newline at end of file
@@ -1,59 +1,204 @@
 use std::{collections::HashSet, ops::Sub};
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use boolinator::Boolinator;
-use colour::{cyan_ln, dark_grey_ln, red_ln};
-use rumqttc::EventLoop;
+use chrono::Local;
+use rumqttc::{EventLoop, SubscribeFilter};
 use rumqttc::Event::Incoming;
-use rumqttc::Packet::Publish;
-
-use crate::{State, Listener, BOOKING_TOPIC};
-use crate::utils::get_power_state;
+use rumqttc::Packet::{ConnAck, Publish};
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, instrument};
+
+use crate::{State, Listener};
+use crate::config::SpacerConfig;
 use crate::utils::logs::{log_debug, machinelog};
 use crate::utils::booking::Booking;
+use crate::utils::detector::Detector;
+use crate::utils::metrics::{MACHINE_RUNTIME, MQTT_MESSAGES, SLAVE_POWERED};
+use crate::utils::relay::Relay;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
 
 impl State<Listener> {
-    pub async fn run(self, mut event_loop: EventLoop) -> ! {
+    pub async fn run(self, mut event_loop: EventLoop, shutdown: CancellationToken) {
+        select! {
+            () = self.run_event_loop(&mut event_loop, &shutdown) => {},
+            never = self.resubscribe_on_reload() => never
+        }
+
+        self.shut_down(event_loop).await;
+    }
+
+    ///rumqttc reconnects on the next poll after an error, so all that's left to do is backing off.
+    ///returns on shutdown, but never while a publish is being handled
+    async fn run_event_loop(&self, event_loop: &mut EventLoop, shutdown: &CancellationToken) {
+        let mut backoff = MIN_BACKOFF;
+
+        loop {
+            let event = select! {
+                event = event_loop.poll() => event,
+                () = shutdown.cancelled() => return
+            };
+
+            match event {
+                Ok(Incoming(Publish(publish))) => {
+                    self.on_publish(publish).await;
+                }
+                Ok(Incoming(ConnAck(_))) => {
+                    info!("connected to MQTT broker");
+                    backoff = MIN_BACKOFF;
+                    self.status.write().await.set_mqtt_connected(true, None);
+                    self.resubscribe().await;
+                }
+                Ok(_) => {}
+                Err(error) => {
+                    error!("MQTT connection failed ~~ {error} - retrying in {}", humantime::format_duration(backoff));
+                    self.status.write().await.set_mqtt_connected(false, Some(error.to_string()));
+                    if shutdown.run_until_cancelled(sleep(backoff)).await.is_none() {
+                        return;
+                    }
+                    backoff = backoff.saturating_mul(2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    async fn resubscribe_on_reload(&self) -> ! {
         loop {
-            let Incoming(Publish(publish)) = event_loop
-                .poll()
-                .await
-                .expect("notification error")
-                else { continue };
-
-            dark_grey_ln!("publish received");
-            self.on_publish(publish).await;
+            self.config_reloaded.notified().await;
+            self.resubscribe().await;
         }
     }
 
+    ///subscriptions don't survive a reconnect with a clean session.
+    ///runs in its own task because the request queue only drains while the event loop gets polled
+    async fn resubscribe(&self) {
+        let client = self.client.read().await.clone();
+        let subscriptions = subscriptions(&self.config());
+
+        tokio::spawn(async move {
+            if let Err(error) = client.subscribe_many(subscriptions).await {
+                error!("failed to subscribe ~~ {error}");
+            }
+        });
+    }
+
+    #[instrument(skip_all, fields(topic = publish.topic))]
     async fn on_publish(&self, publish: rumqttc::Publish) {
         let Ok(payload) = String::from_utf8(publish.payload.clone().into()) else {
-            red_ln!("publish with non-utf8 payload received - {:?}", publish.payload);
+            error!("publish with non-utf8 payload received - {:?}", publish.payload);
             return;
         };
-        dark_grey_ln!("payload: {payload}");
+        debug!(payload, "publish received");
+        self.status.write().await.last_message = Some(Local::now());
+
+        let config = self.config();
+        let machine = affected_machine(&publish.topic, &payload, &config);
+        // a release removes the booking, which is exactly the one worth logging then
+        let booking_before = self.booking_of(machine.as_deref()).await;
 
         let result = self.handle_payload(&publish.topic, &payload).await;
-        
-        log_debug(&publish.topic, &payload, result, &self.config)
-            .expect("debug log failed");
+        self.persist().await;
+
+        MQTT_MESSAGES
+            .with_label_values(&[handler_kind(&publish.topic, &config), result.err().unwrap_or("ok")])
+            .inc();
+
+        let booking = self.booking_of(machine.as_deref()).await.or(booking_before);
+
+        // the debug log isn't worth taking the daemon down, e.g. over a full disk
+        if let Err(error) = log_debug(&publish.topic, &payload, result, machine.as_deref(), booking.as_ref(), &config) {
+            error!("failed to write debug log ~~ {error}");
+        }
+    }
+
+    async fn booking_of(&self, machine: Option<&str>) -> Option<Booking> {
+        self.bookings.read().await.get(machine?).cloned()
     }
 
     async fn handle_payload(&self, topic: &str, payload: &str) -> Result<(), &'static str> {
-        let splits: Result<[_; 3], _> = topic
-            .split('/')
-            .collect::<Vec<_>>()
-            .try_into();
+        let config = self.config();
 
-        match splits {
-            Ok(["tele", machine_name, "MARGINS"])
-                => self.on_machine_activity(payload, &machine_name.into()).await,
+        if topic == config.booking_topic {
+            return self.on_booking_change(payload).await;
+        }
 
-            _ if topic == BOOKING_TOPIC
-                => self.on_booking_change(payload).await,
+        let energy = config
+            .sensors
+            .energy
+            .find(topic)
+            .map(|(machine, meter)| meter.read(payload).map(|total| (machine, total)));
 
-            _   => Err("unknown topic")
+        if let Some(Ok((machine, total))) = &energy {
+            self.on_energy_reading(machine, *total).await;
         }
+
+        if let Some((machine, sensor)) = config.sensors.power.find(topic) {
+            let mut reading = sensor.read(payload)?;
+
+            if let Some(min_dwell) = sensor.min_dwell() {
+                let running = self.bookings.read().await.get(&machine).is_some_and(Booking::is_running);
+
+                reading = self
+                    .detectors
+                    .write()
+                    .await
+                    .entry(machine.clone())
+                    .or_insert_with(|| Detector::starting_as(running))
+                    .feed(reading, min_dwell);
+            }
+
+            let Some(power) = reading else {
+                return Ok(()); // no change
+            };
+
+            return self.on_machine_activity(power, &machine).await;
+        }
+
+        if let Some(result) = energy {
+            return result.map(drop);
+        }
+
+        self.on_slave_state(topic, payload).await
+    }
+
+    async fn on_energy_reading(&self, machine: &str, total: f64) {
+        self.energy_meters
+            .write()
+            .await
+            .insert(machine.to_owned(), total);
+
+        if let Some(booking) = self.bookings.write().await.get_mut(machine) {
+            booking.record_energy(total);
+        }
+    }
+
+    async fn on_slave_state(&self, topic: &str, payload: &str) -> Result<(), &'static str> {
+        let config = self.config();
+        let (slave, props) = config
+            .slave_properties
+            .iter()
+            .find(|(_slave, props)| props.state_topic.as_deref() == Some(topic))
+            .ok_or("unknown topic")?;
+
+        let actual = props.parse_state(payload)?;
+
+        SLAVE_POWERED
+            .with_label_values(&[slave])
+            .set(actual.into());
+
+        let mut relays = self.relays.write().await;
+        let relay = relays
+            .entry(slave.clone())
+            .or_insert_with(|| Relay::observed(actual));
+        relay.actual = Some(actual);
+
+        relay
+            .is_confirmed()
+            .as_result((), "slave reported a different relay state than requested")
     }
 
     async fn on_booking_change(&self, payload: &str) -> Result<(), &'static str> {
@@ -70,27 +215,34 @@ impl State<Listener> {
             _ => return Ok(()) //ignore other statuses
         }
 
-        cyan_ln!("{user} {status} {machine}");
+        info!("{user} {status} {machine}");
 
         Ok(())
     }
 
     async fn try_book(&self, machine: &String, user: &str) -> Result<(), &'static str> {
-        dark_grey_ln!("booking {machine}");
+        debug!("booking {machine}");
+        let energy_meter = self.energy_meters.read().await.get(machine).copied();
         let mut bookings = self.bookings.write().await;
         if bookings.contains_key(machine) {
             drop(bookings); // i really need to stop with these awful hacks
-            dark_grey_ln!("double-booked {machine} - releasing prior");
+            debug!("double-booked {machine} - releasing prior");
             self.try_release(machine).await?;
             bookings = self.bookings.write().await;
         }
-        bookings.insert(machine.clone(), Booking::new(user.to_owned()));
+        bookings.insert(machine.clone(), Booking::new(user.to_owned(), energy_meter));
         drop(bookings);
+        self.reset_detector(machine).await;
         self.update_slaves(machine, false, true, true).await
     }
 
-    async fn try_release(&self, machine: &String) -> Result<(), &'static str> {
-        dark_grey_ln!("releasing {machine}");
+    ///a detector only reports changes. starting over makes a machine that's already running count for the new booking
+    async fn reset_detector(&self, machine: &str) {
+        self.detectors.write().await.remove(machine);
+    }
+
+    pub(super) async fn try_release(&self, machine: &String) -> Result<(), &'static str> {
+        debug!("releasing {machine}");
         let mut booking = self
             .bookings
             .write()
@@ -98,23 +250,27 @@ impl State<Listener> {
             .remove(machine)
             .ok_or("released unbooked machine")?;
 
-        machinelog(machine, &booking, &self.config)
+        self.reset_detector(machine).await;
+
+        machinelog(machine, &booking, &self.config())
             .expect("machine log failed");
 
+        MACHINE_RUNTIME
+            .with_label_values(&[machine])
+            .inc_by(booking.total_runtime().as_secs());
+
         let was_running = booking.track(false);
         self.update_slaves(machine, was_running, true, false).await?;
 
         Ok(())
     }
 
-    async fn on_machine_activity(&self, payload: &str, machine: &String) -> Result<(), &'static str> {
-        let power_string = get_power_state(payload)?;
-
-        let (power, err) =
-            match power_string.as_str() {
-                "ON"  => (true, "machine was turned on while already running"),
-                "OFF" => (false, "machine was turned off without running in the first place"),
-                _     => return Err("unknown power state")
+    async fn on_machine_activity(&self, power: bool, machine: &String) -> Result<(), &'static str> {
+        let (power_string, err) =
+            if power {
+                ("ON", "machine was turned on while already running")
+            } else {
+                ("OFF", "machine was turned off without running in the first place")
             };
 
         self.bookings
@@ -125,7 +281,7 @@ impl State<Listener> {
             .track(power)
             .as_result((), err)?;
 
-        cyan_ln!("info: {machine} got turned {power_string}");
+        info!("{machine} got turned {power_string}");
 
         self.update_slaves(machine, true, false, power).await?;
 
@@ -133,8 +289,9 @@ impl State<Listener> {
     }
 
     pub async fn update_slaves(&self, master: &String, short_slaves: bool, long_slaves: bool, power: bool) -> Result<(), &'static str> {
-        dark_grey_ln!("updating slaves...");
+        debug!("updating slaves...");
 
+        let config = self.config();
         let fallback = HashSet::new();
 
         let slaves_used_by_others = self
@@ -144,31 +301,30 @@ impl State<Listener> {
             .iter()
             .filter(|(other, _booking)| *other != master)
             .flat_map(|(machine, booking)|
-                self.config
+                config
                     .slaves_by_master
                     .get(machine)
                     .unwrap_or(&fallback) // machine being unknown already got logged when it got turned on, so we can ignore it here
                     .iter()
-                    .filter(|slave| booking.is_running() || self.config.slave_properties[*slave].runs_continuously)
+                    .filter(|slave| booking.is_running() || config.slave_properties[*slave].runs_continuously)
             )
             .cloned()
             .collect();
 
-        let slaves_to_update = self
-            .config
+        let slaves_to_update = config
             .slaves_by_master
             .get(master)
             .ok_or("unknown master")?
             .sub(&slaves_used_by_others)
             .into_iter()
-            .filter(|slave| if self.config.slave_properties[slave].runs_continuously { long_slaves } else { short_slaves });
+            .filter(|slave| if config.slave_properties[slave].runs_continuously { long_slaves } else { short_slaves });
 
         for slave in slaves_to_update {
-            if self.config.slave_properties[&slave].needs_trailing_time {
+            if let Some(trailing_time) = config.slave_properties[&slave].trailing_time {
                 if power {
                     self.cancel_scheduled_shutdown(&slave).await;
                 } else {
-                    self.schedule_shutdown(slave).await;
+                    self.schedule_shutdown(slave, trailing_time).await;
                     continue;
                 }
             }
@@ -179,23 +335,64 @@ impl State<Listener> {
         Ok(())
     }
 
-    async fn schedule_shutdown(&self, slave: String) {
-        dark_grey_ln!("scheduling delayed shutdown for {}", slave);
+    async fn schedule_shutdown(&self, slave: String, delay: Duration) {
+        debug!("scheduling delayed shutdown for {} in {}", slave, humantime::format_duration(delay));
 
-        let shutdown_timestamp = Instant::now() + Duration::from_secs(30);
+        let shutdown_timestamp = Local::now() + delay;
 
         self.scheduled_shutdowns
             .write()
             .await
-            .push_back((shutdown_timestamp, slave));
+            .insert(slave, shutdown_timestamp);
+
+        self.schedule_changed.notify_one();
     }
 
     async fn cancel_scheduled_shutdown(&self, slave: &String) {
-        let mut schedule = self.scheduled_shutdowns.write().await;
-
-        if let Some(index) = schedule.iter().position(|(_, name)| name == slave) {
-            dark_grey_ln!("cancelling scheduling shutdown for {}", slave);
-            schedule.remove(index);
+        if self.scheduled_shutdowns.write().await.remove(slave) {
+            debug!("cancelling scheduling shutdown for {}", slave);
+            self.schedule_changed.notify_one();
         }
     }
-}
\ No newline at end of file
+}
+
+fn affected_machine(topic: &str, payload: &str, config: &SpacerConfig) -> Option<String> {
+    if topic == config.booking_topic {
+        return payload.split(';').next().map(str::to_owned);
+    }
+
+    config.sensors.power
+        .find(topic)
+        .map(|(machine, _sensor)| machine)
+        .or_else(|| config.sensors.energy.find(topic).map(|(machine, _meter)| machine))
+}
+
+///what handled the message. a bounded set for the metrics, unlike topics and payloads
+fn handler_kind(topic: &str, config: &SpacerConfig) -> &'static str {
+    if topic == config.booking_topic {
+        "booking"
+    } else if config.sensors.power.find(topic).is_some() {
+        "power"
+    } else if config.sensors.energy.find(topic).is_some() {
+        "energy"
+    } else if config.slave_properties.values().any(|slave| slave.state_topic.as_deref() == Some(topic)) {
+        "slave_state"
+    } else {
+        "unknown"
+    }
+}
+
+fn subscriptions(config: &SpacerConfig) -> Vec<SubscribeFilter> {
+    let state_topics = config
+        .slave_properties
+        .values()
+        .filter_map(|slave| slave.state_topic.clone());
+
+    [config.booking_topic.clone()]
+        .into_iter()
+        .chain(config.sensors.power.subscriptions())
+        .chain(config.sensors.energy.subscriptions())
+        .chain(state_topics)
+        .map(|topic| SubscribeFilter::new(topic, config.mqtt_qos))
+        .collect()
+}
@@ -0,0 +1,164 @@
+use std::io;
+use std::fs;
+use std::collections::HashMap;
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use tap::Pipe;
+use tokio::task;
+use tracing::{debug, error, info, warn};
+
+use crate::config::URN_PREFIX;
+use crate::utils::booking::Booking;
+use crate::utils::detector::Detector;
+use crate::web::fab_api::{self, object::Usage};
+use crate::utils::schedule::Schedule;
+use crate::{Listener, State};
+
+///everything that has to survive a restart
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Snapshot {
+    ///set on a clean shutdown, running bookings didn't run on after this
+    #[serde(default)]
+    stopped_at: Option<DateTime<Local>>,
+    bookings: HashMap<String, Booking>,
+    scheduled_shutdowns: Schedule
+}
+
+impl<Kind> State<Kind> {
+    ///skips the write if nothing changed since the last one
+    pub async fn persist(&self) {
+        self.save(None).await;
+    }
+
+    ///like `persist`, but the downtime until the next start won't count as runtime
+    pub async fn persist_stopped(&self) {
+        self.save(Some(Local::now())).await;
+    }
+
+    async fn save(&self, stopped_at: Option<DateTime<Local>>) {
+        let snapshot = Snapshot {
+            stopped_at,
+            bookings: self.bookings.read().await.clone(),
+            scheduled_shutdowns: self.scheduled_shutdowns.read().await.clone()
+        };
+
+        let serialized = match toml::to_string(&snapshot) {
+            Ok(serialized) => serialized,
+            Err(error) => {
+                error!("failed to serialize state ~~ {error}");
+                return;
+            }
+        };
+
+        // held during the write so concurrent persists can't reorder
+        let mut persisted = self.persisted.lock().await;
+        if *persisted == serialized {
+            return;
+        }
+
+        let path = self.config().state_file.clone();
+        let content = serialized.clone();
+
+        let written = task::spawn_blocking(move || write_snapshot(&content, &path))
+            .await
+            .unwrap_or_else(|error| Err(io::Error::other(error)));
+
+        match written {
+            Ok(()) => *persisted = serialized,
+            Err(error) => error!("failed to persist state ~~ {error}")
+        }
+    }
+
+    pub async fn restore(&self) {
+        let mut snapshot = match read_snapshot(&self.config().state_file) {
+            Ok(snapshot) => snapshot,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return,
+            Err(error) => {
+                error!("failed to restore state, starting without bookings ~~ {error}");
+                return;
+            }
+        };
+
+        debug!("restored {} bookings and {} scheduled shutdowns", snapshot.bookings.len(), snapshot.scheduled_shutdowns.len());
+
+        if let Some(stopped_at) = snapshot.stopped_at {
+            for booking in snapshot.bookings.values_mut() {
+                booking.skip_downtime(stopped_at);
+            }
+        }
+
+        // a fresh detector would take machines for stopped and never report them turning off
+        *self.detectors.write().await = snapshot
+            .bookings
+            .iter()
+            .map(|(machine, booking)| (machine.clone(), Detector::starting_as(booking.is_running())))
+            .collect();
+
+        *self.bookings.write().await = snapshot.bookings;
+        *self.scheduled_shutdowns.write().await = snapshot.scheduled_shutdowns;
+    }
+}
+
+impl State<Listener> {
+    ///releases restored bookings whose machines got released in FabAccess while we were down
+    pub async fn reconcile(&self) {
+        let booked_machines = self
+            .bookings
+            .read()
+            .await
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if booked_machines.is_empty() {
+            return;
+        }
+
+        let config = self.config();
+        let (Some(username), Some(password)) = (&config.fabaccess_username, &config.fabaccess_password) else {
+            warn!("no FabAccess credentials configured, restored bookings can't be reconciled");
+            return;
+        };
+
+        let resources = match fab_api::get_resources(username, password, None, &config).await {
+            Ok(resources) => resources,
+            Err(error) => {
+                error!("failed to reconcile restored bookings ~~ {error}");
+                return;
+            }
+        };
+
+        for machine in booked_machines {
+            let still_in_use = resources
+                .iter()
+                .find(|resource| resource.urn.trim_start_matches(URN_PREFIX) == machine)
+                .is_none_or(|resource| resource.usage != Usage::Free);
+
+            if still_in_use {
+                continue;
+            }
+
+            info!("{machine} got released while spacermake was down");
+            if let Err(error) = self.try_release(&machine).await {
+                error!("failed to release {machine} ~~ {error}");
+            }
+        }
+
+        self.persist().await;
+    }
+}
+
+fn read_snapshot(path: &str) -> io::Result<Snapshot> {
+    fs::read_to_string(path)?
+        .pipe_as_ref(toml::from_str)
+        .map_err(io::Error::other)
+}
+
+///writes to a temporary file first so a crash mid-write can't corrupt the previous state
+fn write_snapshot(serialized: &str, path: &str) -> io::Result<()> {
+    let temporary = format!("{path}.tmp");
+
+    fs::write(&temporary, serialized)?;
+    fs::rename(temporary, path)
+}
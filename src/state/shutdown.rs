@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use futures::future::join;
+use rumqttc::{Event, EventLoop, Outgoing};
+use tokio::time::timeout;
+use tracing::{error, info, warn};
+
+use crate::{Listener, State};
+
+///how long the broker gets to take the last messages before we give up on it
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl State<Listener> {
+    ///powers off slaves still in their trailing time and disconnects from the broker.
+    ///open bookings stay in the state file and get reconciled on the next start
+    pub async fn shut_down(&self, mut event_loop: EventLoop) {
+        info!("shutting down");
+
+        // in case the broker is gone, the scheduled shutdowns are still on disk
+        self.persist_stopped().await;
+
+        let open_bookings = self.bookings.read().await.len();
+        if open_bookings > 0 {
+            info!("{open_bookings} open bookings saved for the next start");
+        }
+
+        let pending = self.scheduled_shutdowns.write().await.drain();
+
+        let power_off = async {
+            for slave in &pending {
+                info!("powering off {slave} ahead of its scheduled shutdown");
+                self.set_power_state(slave, false).await;
+            }
+
+            if let Err(error) = self.client.read().await.disconnect().await {
+                error!("failed to disconnect from MQTT broker ~~ {error}");
+            }
+        };
+
+        // requests only get sent while the event loop is polled
+        let flush = async {
+            loop {
+                match event_loop.poll().await {
+                    Ok(Event::Outgoing(Outgoing::Disconnect)) => return true,
+                    Ok(_) => {}
+                    Err(error) => {
+                        error!("MQTT connection failed during shutdown ~~ {error}");
+                        return false;
+                    }
+                }
+            }
+        };
+
+        match timeout(DISCONNECT_TIMEOUT, join(power_off, flush)).await {
+            Ok(((), true)) => {
+                info!("disconnected from MQTT broker");
+                self.persist_stopped().await;
+            }
+            _ => warn!("MQTT broker unreachable, {} scheduled shutdowns stay saved for the next start", pending.len())
+        }
+    }
+}
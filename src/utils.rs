@@ -1,23 +1,29 @@
 use std::time::Duration;
 
+use chrono::{DateTime, Local};
+
 pub mod logs;
 pub mod booking;
+pub mod detector;
+pub mod metrics;
+pub mod relay;
+pub mod schedule;
+pub mod status;
+#[cfg(test)]
+pub mod temp_dir;
+
+///wall-clock time passed since `timestamp`, zero if the clock went backwards in the meantime
+pub fn elapsed_since(timestamp: DateTime<Local>) -> Duration {
+    (Local::now() - timestamp)
+        .to_std()
+        .unwrap_or_default()
+}
 
-pub fn get_power_state(payload: &str) -> Result<String, &'static str> {
-    //todo: there gotta be an easier way to do this
-    json::parse(payload)
-        .map_err(|_| "payload is not a valid json string")?
-        .entries()
-        .find(|(key, _value)| *key == "MARGINS")
-        .ok_or("no MARGINS data present in payload")?
-        .1
-        .entries()
-        .find(|(key, _value)| *key == "PowerHigh")
-        .ok_or("no powerHigh information")?
-        .1
-        .as_str()
-        .ok_or("powerHigh state was not a string")
-        .map(str::to_string)
+///wall-clock time left until `timestamp`, zero if it already passed
+pub fn time_until(timestamp: DateTime<Local>) -> Duration {
+    (timestamp - Local::now())
+        .to_std()
+        .unwrap_or_default()
 }
 
 ///whether this duration crossed a minute boundary within the last second
@@ -1,23 +1,33 @@
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use chrono::{DateTime, Local};
+use json::{object, JsonValue};
+use serde::{Deserialize, Serialize};
 
+use crate::utils::elapsed_since;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Booking {
     pub user: String,
     pub creation_datetime: DateTime<Local>,
-    pub creation_instant: Instant,
-    pub currently_running_since: Option<Instant>,
-    pub runtime_accumulator: Duration
+    pub currently_running_since: Option<DateTime<Local>>,
+    pub runtime_accumulator: Duration,
+    ///energy meter readings in kWh, at booking (or the first reading after) and the latest one
+    #[serde(default)]
+    pub energy_at_booking: Option<f64>,
+    #[serde(default)]
+    pub energy_latest: Option<f64>
 }
 
 impl Booking {
-    pub fn new(user: String) -> Self {
+    pub fn new(user: String, energy_meter: Option<f64>) -> Self {
         Self {
             user,
             creation_datetime: Local::now(),
-            creation_instant: Instant::now(),
             currently_running_since: None,
-            runtime_accumulator: Duration::ZERO
+            runtime_accumulator: Duration::ZERO,
+            energy_at_booking: energy_meter,
+            energy_latest: energy_meter
         }
     }
 
@@ -27,18 +37,26 @@ impl Booking {
         }
 
         if power {
-            self.currently_running_since = Some(Instant::now());
+            self.currently_running_since = Some(Local::now());
         } else {
             self.runtime_accumulator += self
                 .currently_running_since
                 .take()
-                .unwrap() //SAFETY: guaranteed by .is_running()
-                .elapsed();
+                .map(elapsed_since)
+                .unwrap(); //SAFETY: guaranteed by .is_running()
         }
 
         true
     }
 
+    ///counts the runtime up to `stopped_at` and resumes from now, so the time spacermake was down isn't billed
+    pub fn skip_downtime(&mut self, stopped_at: DateTime<Local>) {
+        if let Some(since) = self.currently_running_since {
+            self.runtime_accumulator += (stopped_at - since).to_std().unwrap_or_default();
+            self.currently_running_since = Some(Local::now());
+        }
+    }
+
     pub const fn is_running(&self) -> bool {
         self.currently_running_since.is_some()
     }
@@ -47,9 +65,34 @@ impl Booking {
         let mut total = self.runtime_accumulator;
 
         if let Some(startup) = self.currently_running_since {
-            total += startup.elapsed();
+            total += elapsed_since(startup);
         }
 
         total
     }
-}
\ No newline at end of file
+
+    pub fn booked_duration(&self) -> Duration {
+        elapsed_since(self.creation_datetime)
+    }
+
+    pub fn record_energy(&mut self, meter: f64) {
+        self.energy_at_booking.get_or_insert(meter);
+        self.energy_latest = Some(meter);
+    }
+
+    ///kWh consumed so far, `None` if the machine's meter never reported.
+    ///a meter that got reset in between counts as nothing consumed
+    pub fn consumed_energy(&self) -> Option<f64> {
+        Some((self.energy_latest? - self.energy_at_booking?).max(0.0))
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        object! {
+            user: self.user.clone(),
+            booked_since: self.creation_datetime.to_rfc3339(),
+            running_since: self.currently_running_since.map(|since| since.to_rfc3339()),
+            runtime_seconds: self.total_runtime().as_secs(),
+            energy_kwh: self.consumed_energy()
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::time::{Duration, Instant};
+
+///debounces power readings, so a machine idling around the threshold doesn't count as switching
+#[derive(Debug, Default)]
+pub struct Detector {
+    pub running: bool,
+    ///the state readings currently point to, and since when
+    candidate: Option<(bool, Instant)>
+}
+
+impl Detector {
+    ///picks up from a state known otherwise, e.g. a booking restored while its machine was running
+    pub fn starting_as(running: bool) -> Self {
+        Self { running, candidate: None }
+    }
+
+    ///returns the new state once readings stayed on the other side for `min_dwell`.
+    ///`None` readings (within the hysteresis) restart the dwell time
+    pub fn feed(&mut self, reading: Option<bool>, min_dwell: Duration) -> Option<bool> {
+        let Some(reading) = reading.filter(|reading| *reading != self.running) else {
+            self.candidate = None;
+            return None;
+        };
+
+        let since = match self.candidate {
+            Some((candidate, since)) if candidate == reading => since,
+            _ => self.candidate.insert((reading, Instant::now())).1
+        };
+
+        if since.elapsed() < min_dwell {
+            return None;
+        }
+
+        self.running = reading;
+        self.candidate = None;
+        Some(reading)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switches_at_once_without_dwell_time() {
+        let mut detector = Detector::default();
+
+        assert_eq!(detector.feed(Some(true), Duration::ZERO), Some(true));
+        assert_eq!(detector.feed(Some(true), Duration::ZERO), None);
+        assert_eq!(detector.feed(Some(false), Duration::ZERO), Some(false));
+        assert!(!detector.running);
+    }
+
+    #[test]
+    fn a_detector_started_as_running_reports_turning_off() {
+        let mut detector = Detector::starting_as(true);
+
+        assert_eq!(detector.feed(Some(false), Duration::ZERO), Some(false));
+    }
+
+    #[test]
+    fn waits_for_the_dwell_time() {
+        let mut detector = Detector::default();
+        let min_dwell = Duration::from_millis(20);
+
+        assert_eq!(detector.feed(Some(true), min_dwell), None);
+        std::thread::sleep(min_dwell);
+        assert_eq!(detector.feed(Some(true), min_dwell), Some(true));
+        assert!(detector.running);
+    }
+
+    #[test]
+    fn readings_within_the_hysteresis_restart_the_dwell_time() {
+        let mut detector = Detector::default();
+        let min_dwell = Duration::from_millis(20);
+
+        assert_eq!(detector.feed(Some(true), min_dwell), None);
+        std::thread::sleep(min_dwell);
+        assert_eq!(detector.feed(None, min_dwell), None);
+        assert_eq!(detector.feed(Some(true), min_dwell), None);
+        assert!(!detector.running);
+    }
+
+    #[test]
+    fn readings_matching_the_state_restart_the_dwell_time() {
+        let mut detector = Detector { running: true, candidate: None };
+        let min_dwell = Duration::from_millis(20);
+
+        assert_eq!(detector.feed(Some(false), min_dwell), None);
+        std::thread::sleep(min_dwell);
+        assert_eq!(detector.feed(Some(true), min_dwell), None);
+        assert_eq!(detector.feed(Some(false), min_dwell), None);
+        assert!(detector.running);
+    }
+}
@@ -1,17 +1,36 @@
-use std::{io::Write, ops::Div};
-use std::io;
-use std::fs::File;
+use std::{io::Write, ops::{Div, Mul}};
+use std::io::{self, BufRead, BufReader};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::{self, File};
+use std::os::unix::fs::MetadataExt;
+use std::sync::{LazyLock, Mutex, PoisonError};
 
 use chrono::Local;
-use colour::red_ln;
 use csv::WriterBuilder;
+use json::{object, JsonValue};
 use serde::Serialize;
+use tap::Pipe;
+use tracing::error;
 
 use crate::{config::SpacerConfig, utils::booking::Booking};
+use crate::config::log_file::LogFile;
 
 use self::billing::billinglog;
+use self::rotation::{rotate, rotate_if_due};
 
 pub mod billing;
+pub mod rotation;
+
+///JSON lines files already known to fit, with their inode and schema version. the debug log gets written on every message,
+///so its first line is only read again once the file got replaced
+static JSON_LINES_CHECKED: LazyLock<Mutex<HashMap<String, (u64, u32)>>> = LazyLock::new(Mutex::default);
+
+///bump whenever `Record` changes. 1 was the headerless layout
+const MACHINELOG_SCHEMA: u32 = 2;
+///bump whenever the debug log's fields change. 1 covers free-form text and JSON lines without a version
+const DEBUG_LOG_SCHEMA: u32 = 2;
+pub const SCHEMA_COLUMN: &str = "schema_version";
 
 #[derive(Debug, Serialize)]
 struct Record<'string> {
@@ -21,57 +40,149 @@ struct Record<'string> {
     time_released: String,
     booking_duration: i32, //minutes
     runtime: i32, //minutes
-    user: &'string str
+    user: &'string str,
+    energy: Option<f64> //kWh
 }
 
 pub fn machinelog(machine: &str, booking: &Booking, config: &SpacerConfig) -> io::Result<()> {
-    billinglog(machine, booking, config)?;
+    // a row the billing format refuses shouldn't take the machine log down with it
+    if let Err(error) = billinglog(machine, booking, config) {
+        error!("booking of {machine} by {} not billed ~~ {error}", booking.user);
+    }
 
     let record = Record {
         machine,
         date: booking.creation_datetime.date_naive().to_string(),
         time_booked: booking.creation_datetime.time().to_string(),
         time_released: Local::now().time().to_string(),
-        booking_duration: booking.creation_instant.elapsed().as_secs_f32().div(60.0).ceil() as _,
+        booking_duration: booking.booked_duration().as_secs_f32().div(60.0).ceil() as _,
         runtime: booking.total_runtime().as_secs_f32().div(60.0).ceil() as _,
-        user: &booking.user
+        user: &booking.user,
+        energy: booking.consumed_energy().map(|kwh| kwh.mul(1000.0).round().div(1000.0))
     };
 
-    let file_writer = File::options()
-        .create(true)
-        .append(true)
-        .open(&config.machine_log)?;
+    rotate_if_due(&config.machine_log);
+    append_csv(&config.machine_log, b',', MACHINELOG_SCHEMA, &record)
+}
 
-    WriterBuilder::new()
-        .has_headers(false)
-        .from_writer(file_writer)
-        .serialize(&record)
+///appends a row, prefixed with the schema version. new files start with a header.
+///a file with a different header or schema version gets rotated away first, so every file has a single schema
+pub fn append_csv(log: &LogFile, delimiter: u8, schema_version: u32, record: &(impl Serialize + Debug)) -> io::Result<()> {
+    let mut writer = WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(Vec::new());
+
+    writer
+        .serialize(record)
         .map_err(|error| {
-            red_ln!("error while serializing: {error}\n{record:#?}");
-            io::ErrorKind::Other.into()
-        })
-}
+            error!("failed to serialize: {error}\n{record:#?}");
+            io::Error::from(io::ErrorKind::Other)
+        })?;
 
-pub fn log_debug(topic: &str, payload: &str, result: Result<(), &str>, config: &SpacerConfig) -> io::Result<()> {
-    if let Err(error) = result {
-        red_ln!("error: {error}");
-        red_ln!("	topic: {topic}");
-        red_ln!("	payload: {payload}");
-        red_ln!();
+    let text = writer
+        .into_inner()
+        .map_err(|error| error.into_error())
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())?;
+
+    let mut lines = text.lines();
+    let delimiter = char::from(delimiter);
+    let header = format!("{SCHEMA_COLUMN}{delimiter}{}", lines.next().unwrap_or_default());
+    let row = format!("{schema_version}{delimiter}{}", lines.next().unwrap_or_default());
+
+    if !has_schema(&log.path, &header, &format!("{schema_version}{delimiter}"))? {
+        rotate_now(log)?;
     }
 
-    let time = Local::now().to_string();
-    let result = result.err().unwrap_or("ok");
+    let mut file = File::options()
+        .create(true)
+        .append(true)
+        .open(&log.path)?;
+
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "{header}")?;
+    }
+    writeln!(file, "{row}")
+}
 
-    let record = format!("
-time:    {time}
-topic:   {topic}
-payload: {payload}
-result:  {result}",
-    );
+fn has_schema(path: &str, header: &str, row_prefix: &str) -> io::Result<bool> {
+    head(path)?
+        .is_none_or(|(first, second)| first == header && second.is_none_or(|row| row.starts_with(row_prefix)))
+        .pipe(Ok)
+}
+
+///appends a JSON object carrying `schema_version`.
+///a file that isn't JSON lines of that schema version gets rotated away first, like in `append_csv`
+pub fn append_json_line(log: &LogFile, schema_version: u32, line: &JsonValue) -> io::Result<()> {
+    ensure_json_schema(log, schema_version)?;
 
     File::options()
+        .create(true)
         .append(true)
-        .open(&config.debug_log)?
-        .write_all(record.as_bytes())
-}
\ No newline at end of file
+        .open(&log.path)?
+        .pipe(|mut file| writeln!(file, "{}", line.dump()))
+}
+
+fn ensure_json_schema(log: &LogFile, schema_version: u32) -> io::Result<()> {
+    let inode = match fs::metadata(&log.path) {
+        Ok(metadata) => metadata.ino(),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(error)
+    };
+
+    let mut checked = JSON_LINES_CHECKED.lock().unwrap_or_else(PoisonError::into_inner);
+    if checked.get(&log.path) == Some(&(inode, schema_version)) {
+        return Ok(());
+    }
+
+    let fits = head(&log.path)?.is_none_or(|(first, _second)| {
+        json::parse(&first).is_ok_and(|json| json[SCHEMA_COLUMN].as_u32() == Some(schema_version))
+    });
+
+    if fits {
+        checked.insert(log.path.clone(), (inode, schema_version));
+        Ok(())
+    } else {
+        rotate_now(log)
+    }
+}
+
+///the first two lines. `None` for missing and empty files, they fit any schema
+fn head(path: &str) -> io::Result<Option<(String, Option<String>)>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error)
+    };
+
+    let mut lines = BufReader::new(file).lines();
+    let Some(first) = lines.next().transpose()? else {
+        return Ok(None);
+    };
+
+    Ok(Some((first, lines.next().transpose()?)))
+}
+
+///moves a file that doesn't fit its schema out of the way
+fn rotate_now(log: &LogFile) -> io::Result<()> {
+    rotate(log, &Local::now().format("%Y%m%d-%H%M%S").to_string())
+}
+
+///one JSON object per line. the booking is the machine's state after handling the message, or before if it got released
+pub fn log_debug(topic: &str, payload: &str, result: Result<(), &str>, machine: Option<&str>, booking: Option<&Booking>, config: &SpacerConfig) -> io::Result<()> {
+    if let Err(error) = result {
+        error!(topic, payload, "{error}");
+    }
+
+    let record = object! {
+        schema_version: DEBUG_LOG_SCHEMA,
+        time: Local::now().to_rfc3339(),
+        topic: topic,
+        payload: payload,
+        result: result.err().unwrap_or("ok"),
+        machine: machine,
+        booking: booking.map(Booking::to_json)
+    };
+
+    rotate_if_due(&config.debug_log);
+    append_json_line(&config.debug_log, DEBUG_LOG_SCHEMA, &record)
+}
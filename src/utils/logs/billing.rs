@@ -1,24 +1,19 @@
-use std::{io, ops::Div};
-use std::fs::File;
+use std::{io, ops::Mul};
+use std::path::Path;
 
 use chrono::Local;
-use colour::red_ln;
-use csv::WriterBuilder;
-use serde::Serialize;
+use tracing::error;
 
 use crate::utils::booking::Booking;
 use crate::config::{MachineData, SpacerConfig};
+use crate::config::billing::BillingRule;
+use crate::config::log_file::{LogFile, Rotation};
 
-#[derive(Debug, Serialize)]
-struct BillingRecord {
-    user_id: String,                      // id nachschlagen in DataUser.csv. Wenn Spalte 3 ("toBeUsed") == 0 dann skip. Wenn nicht vorhanden dann fallback zum Namen
-    quelle: &'static str,                 // "allgemeiner Beleg"
-    brutto_netto: i32,                    // 2
-    artikel_id: String,                   // DataMachine.csv#2
-    positionsdetails: String,             // Date
-    anzahl: i32,                          // minutes divided by DataMachine.csv#5 (ceil)
-    rechnungstyp: i32                     // 0
-}
+use super::rotation::rotate_if_due;
+
+use self::export::{BillingExporter, JsonLines, LineItem};
+
+pub mod export;
 
 pub fn billinglog(machine: &str, booking: &Booking, config: &SpacerConfig) -> io::Result<()> {
     let user_id =
@@ -30,58 +25,75 @@ pub fn billinglog(machine: &str, booking: &Booking, config: &SpacerConfig) -> io
         } else {
             booking.user.clone()
         };
+
+    let gruppe = config
+        .data_user
+        .get(&booking.user)
+        .and_then(|user_data| user_data.group.clone());
+
+    let group = gruppe
+        .as_ref()
+        .and_then(|gruppe| config.user_groups.get(gruppe));
         
-    let machine_data = &config
+    let fallback = MachineData {
+        id: None,
+        to_be_used: true,
+        billing_rule: BillingRule::default()
+    };
+    let machine_data = config
         .data_machines
         .get(machine)
-        .unwrap_or(&MachineData {
-            id: None,
-            to_be_used: true,
-            power_sense: true,
-            divider: 1
-        });
+        .unwrap_or(&fallback);
         
     if !machine_data.to_be_used { return Ok(()); }
     
-    let artikel_id = machine_data
-        .id
+    let article_id = group
+        .and_then(|group| group.artikel_id(machine))
+        .or(machine_data.id)
         .map_or_else(|| machine.to_owned(), |i| i.to_string());
     
-    let anzahl =
-        if machine_data.power_sense {
-            booking.total_runtime()
-        } else {
-            booking.creation_instant.elapsed()
-        }
-        .as_secs_f32()
-        .div(60.0)
-        .div(machine_data.divider as f32)
+    let usage = config
+        .billing_rules
+        .get(machine)
+        .unwrap_or(&machine_data.billing_rule)
+        .quantity(machine, booking);
+
+    let quantity = f64::from(usage)
+        .mul(group.map_or(1.0, |group| group.multiplier(machine)))
         .ceil()
         as _;
     
-    let bill = BillingRecord {
+    let item = LineItem {
         user_id,
-        quelle: "allgemeiner Beleg",
-        brutto_netto: 2,
-        artikel_id,
-        positionsdetails: Local::now()
-            .format("%Y-%m-%d")
-            .to_string(),
-        anzahl,
-        rechnungstyp: 0,
+        user: booking.user.clone(),
+        machine: machine.to_owned(),
+        article_id,
+        date: Local::now().date_naive(),
+        quantity,
+        group: gruppe
     };
     
-    let file_writer = File::options()
-        .create(true)
-        .append(true)
-        .open(&config.billing_log)?;
+    rotate_if_due(&config.billing_log);
+
+    // billing data is legally relevant, a refused line item has to stay somewhere
+    if let Err(error) = config.billing_exporter.export(&item, &config.billing_log) {
+        let rejects = rejects_log(&config.billing_log);
+        error!("billing log refused the booking of {machine} by {}, keeping it in {} ~~ {error}", booking.user, rejects.path);
+        JsonLines.export(&item, &rejects)?;
+    }
+
+    Ok(())
+}
+
+///next to the billing log, but not named like one of its rotations so imports don't pick it up
+fn rejects_log(billing_log: &LogFile) -> LogFile {
+    let path = Path::new(&billing_log.path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
 
-    WriterBuilder::new()
-        .has_headers(false)
-        .from_writer(file_writer)
-        .serialize(&bill)
-        .map_err(|error| {
-            red_ln!("error while serializing: {error}\n{bill:#?}");
-            io::ErrorKind::Other.into()
-        })
+    LogFile {
+        path: path.with_file_name(format!("{stem}_rejected.jsonl")).to_string_lossy().into_owned(),
+        rotation: Rotation::Never,
+        compress: false,
+        retention: None
+    }
 }
\ No newline at end of file
@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::{self, BufRead, BufReader, Read};
+
+use chrono::{Datelike, Local, NaiveDate};
+use csv::ReaderBuilder;
+use json::object;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use tracing::error;
+
+use crate::config::{MachineData, UserData};
+use crate::config::log_file::LogFile;
+use crate::utils::logs::{append_csv, append_json_line, SCHEMA_COLUMN};
+
+///bump whenever a record layout changes. 1 was the headerless legacy layout
+const LEGACY_SCHEMA: u32 = 2;
+const DATEV_SCHEMA: u32 = 2;
+const JSON_LINES_SCHEMA: u32 = 1;
+
+///one billed booking, before it gets shaped for the accounting software
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineItem {
+    pub user_id: String,                  // id from DataUser.csv, falls back to the name
+    pub user: String,
+    pub machine: String,
+    pub article_id: String,               // DataMachine.csv#2, or the group's override
+    pub date: NaiveDate,
+    pub quantity: i32,                    // see BillingRule and UserGroup
+    pub group: Option<String>             // DataUser.csv#4
+}
+
+///a billing log layout, selected by BILLING_FORMAT
+pub trait BillingExporter: Debug + Send + Sync {
+    fn export(&self, item: &LineItem, log: &LogFile) -> io::Result<()>;
+
+    ///reads a log back in. fields the layout doesn't carry get filled in as good as possible:
+    ///the user falls back to `user_id` and the machine to `article_id`.
+    ///rows that fail to parse are reported and skipped
+    fn import(&self, reader: Box<dyn Read>) -> Vec<LineItem>;
+
+    ///what would keep known users and machines from being exported, checked during validation
+    fn problems(&self, _data_user: &HashMap<String, UserData>, _data_machines: &HashMap<String, MachineData>) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+///the layout our accounting software has always imported
+#[derive(Debug)]
+pub struct Legacy {
+    pub quelle: String,                   // "allgemeiner Beleg"
+    pub brutto_netto: i32,                // 2
+    pub rechnungstyp: i32                 // 0
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LegacyRecord {
+    user_id: String,
+    quelle: String,
+    brutto_netto: i32,
+    artikel_id: String,
+    positionsdetails: NaiveDate,
+    anzahl: i32,
+    rechnungstyp: i32,
+    #[serde(default)]
+    gruppe: Option<String>
+}
+
+impl BillingExporter for Legacy {
+    fn export(&self, item: &LineItem, log: &LogFile) -> io::Result<()> {
+        let record = LegacyRecord {
+            user_id: item.user_id.clone(),
+            quelle: self.quelle.clone(),
+            brutto_netto: self.brutto_netto,
+            artikel_id: item.article_id.clone(),
+            positionsdetails: item.date,
+            anzahl: item.quantity,
+            rechnungstyp: self.rechnungstyp,
+            gruppe: item.group.clone()
+        };
+
+        append_csv(log, b',', LEGACY_SCHEMA, &record)
+    }
+
+    fn import(&self, reader: Box<dyn Read>) -> Vec<LineItem> {
+        read_csv(reader, b',')
+            .into_iter()
+            .map(|record: LegacyRecord| LineItem {
+                user: record.user_id.clone(),
+                user_id: record.user_id,
+                machine: record.artikel_id.clone(),
+                article_id: record.artikel_id,
+                date: record.positionsdetails,
+                quantity: record.anzahl,
+                group: record.gruppe
+            })
+            .collect()
+    }
+}
+
+///DATEV style booking rows, users are the debtor accounts
+#[derive(Debug)]
+pub struct Datev {
+    pub gegenkonto: String,               // revenue account
+    pub bu_schluessel: String,            // tax key, may be empty
+    pub prices: HashMap<String, f64>      // per article id, BILLING_PRICES
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DatevRecord {
+    #[serde(default)]
+    umsatz: String,                       // decimal comma, missing before schema 2
+    menge: i32,
+    soll_haben: String,
+    konto: String,
+    gegenkonto: String,
+    bu_schluessel: String,
+    belegdatum: String,                   // DDMM, the year comes from the import batch
+    belegfeld1: String,                   // article
+    buchungstext: String,                 // machine/user, machine names can't contain a slash
+    kost1: String                         // group
+}
+
+impl BillingExporter for Datev {
+    ///rows without a numeric account or a price couldn't be imported, so they're refused
+    fn export(&self, item: &LineItem, log: &LogFile) -> io::Result<()> {
+        if item.user_id.parse::<u32>().is_err() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} has no numeric id to use as DATEV account", item.user)));
+        }
+
+        let price = self.prices.get(&item.article_id).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("no price for article {} in BILLING_PRICES", item.article_id))
+        })?;
+
+        let record = DatevRecord {
+            umsatz: format!("{:.2}", f64::from(item.quantity) * price).replace('.', ","),
+            menge: item.quantity,
+            soll_haben: "S".into(),
+            konto: item.user_id.clone(),
+            gegenkonto: self.gegenkonto.clone(),
+            bu_schluessel: self.bu_schluessel.clone(),
+            belegdatum: item.date.format("%d%m").to_string(),
+            belegfeld1: item.article_id.clone(),
+            buchungstext: format!("{}/{}", item.machine, item.user),
+            kost1: item.group.clone().unwrap_or_default()
+        };
+
+        append_csv(log, b';', DATEV_SCHEMA, &record)
+    }
+
+    ///the year is assumed to be the most recent one that doesn't put the row into the future
+    fn import(&self, reader: Box<dyn Read>) -> Vec<LineItem> {
+        let today = Local::now().date_naive();
+
+        read_csv(reader, b';')
+            .into_iter()
+            .filter_map(|record: DatevRecord| {
+                let date = [today.year(), today.year() - 1]
+                    .into_iter()
+                    .filter_map(|year| NaiveDate::parse_from_str(&format!("{}{year}", record.belegdatum), "%d%m%Y").ok())
+                    .find(|date| *date <= today);
+
+                let Some(date) = date else {
+                    error!("invalid Belegdatum `{}` in billing log", record.belegdatum);
+                    return None;
+                };
+
+                let (machine, user) = record
+                    .buchungstext
+                    .split_once('/')
+                    .unwrap_or((&record.belegfeld1, &record.konto));
+
+                Some(LineItem {
+                    machine: machine.to_owned(),
+                    user: user.to_owned(),
+                    user_id: record.konto,
+                    article_id: record.belegfeld1,
+                    date,
+                    quantity: record.menge,
+                    group: Some(record.kost1).filter(|group| !group.is_empty())
+                })
+            })
+            .collect()
+    }
+
+    fn problems(&self, data_user: &HashMap<String, UserData>, data_machines: &HashMap<String, MachineData>) -> Vec<String> {
+        let accounts = data_user
+            .iter()
+            .filter(|(_user, data)| data.to_be_used && data.id.is_none_or(|id| id < 0))
+            .map(|(user, _data)| format!("user `{user}` needs a numeric id as DATEV account"));
+
+        let prices = data_machines
+            .iter()
+            .filter(|(_machine, data)| data.to_be_used)
+            .map(|(machine, data)| (machine, data.id.map_or_else(|| machine.clone(), |id| id.to_string())))
+            .filter(|(_machine, article_id)| !self.prices.contains_key(article_id))
+            .map(|(machine, article_id)| format!("article `{article_id}` of `{machine}` has no price in BILLING_PRICES"));
+
+        accounts.chain(prices).collect()
+    }
+}
+
+///one JSON object per line
+#[derive(Debug)]
+pub struct JsonLines;
+
+impl BillingExporter for JsonLines {
+    fn export(&self, item: &LineItem, log: &LogFile) -> io::Result<()> {
+        let line = object! {
+            schema_version: JSON_LINES_SCHEMA,
+            user_id: item.user_id.clone(),
+            user: item.user.clone(),
+            machine: item.machine.clone(),
+            article_id: item.article_id.clone(),
+            date: item.date.to_string(),
+            quantity: item.quantity,
+            group: item.group.clone()
+        };
+
+        append_json_line(log, JSON_LINES_SCHEMA, &line)
+    }
+
+    fn import(&self, reader: Box<dyn Read>) -> Vec<LineItem> {
+        BufReader::new(reader)
+            .lines()
+            .map_while(Result::ok)
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| {
+                let item = json::parse(&line).ok().and_then(|json| Some(LineItem {
+                    user_id: json["user_id"].as_str()?.to_owned(),
+                    user: json["user"].as_str()?.to_owned(),
+                    machine: json["machine"].as_str()?.to_owned(),
+                    article_id: json["article_id"].as_str()?.to_owned(),
+                    date: json["date"].as_str()?.parse().ok()?,
+                    quantity: json["quantity"].as_i32()?,
+                    group: json["group"].as_str().map(str::to_owned)
+                }));
+
+                if item.is_none() {
+                    error!("invalid line in billing log ~~ {line}");
+                }
+                item
+            })
+            .collect()
+    }
+}
+
+///rows are matched by header if there is one, by position otherwise
+fn read_csv<Record: DeserializeOwned>(mut reader: Box<dyn Read>, delimiter: u8) -> Vec<Record> {
+    let mut content = String::new();
+    if let Err(error) = reader.read_to_string(&mut content) {
+        error!("failed to read billing log ~~ {error}");
+    }
+
+    ReaderBuilder::new()
+        .has_headers(content.starts_with(SCHEMA_COLUMN))
+        .delimiter(delimiter)
+        .flexible(true) // headerless rows from before the group column
+        .from_reader(content.as_bytes())
+        .into_deserialize()
+        .filter_map(|record| {
+            record
+                .map_err(|error| error!("invalid row in billing log ~~ {error}"))
+                .ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::path::PathBuf;
+
+    use crate::config::log_file::Rotation;
+    use crate::utils::temp_dir::TempDir;
+
+    use super::*;
+
+    ///a fresh log in its own directory, rotated files end up next to it
+    fn log(directory: &TempDir) -> LogFile {
+        LogFile {
+            path: directory.path().join("billinglog.csv").to_string_lossy().into_owned(),
+            rotation: Rotation::Never,
+            compress: false,
+            retention: None
+        }
+    }
+
+    fn items() -> [LineItem; 2] {
+        let today = Local::now().date_naive();
+        [
+            LineItem {
+                user_id: "17".into(),
+                user: "alice".into(),
+                machine: "Laser Cutter".into(),
+                article_id: "5".into(),
+                date: today,
+                quantity: 3,
+                group: Some("student".into())
+            },
+            LineItem {
+                user_id: "23".into(),
+                user: "bob".into(),
+                machine: "Saw".into(),
+                article_id: "6".into(),
+                date: today,
+                quantity: 1,
+                group: None
+            }
+        ]
+    }
+
+    fn round_trip(exporter: &dyn BillingExporter, log: &LogFile) -> Vec<LineItem> {
+        for item in items() {
+            exporter.export(&item, log).expect("export");
+        }
+
+        exporter.import(Box::new(File::open(&log.path).expect("billing log")))
+    }
+
+    fn datev() -> Datev {
+        Datev {
+            gegenkonto: "8400".into(),
+            bu_schluessel: String::new(),
+            prices: HashMap::from([("5".into(), 0.5), ("6".into(), 1.25)])
+        }
+    }
+
+    #[test]
+    fn legacy_round_trip() {
+        let legacy = Legacy { quelle: "allgemeiner Beleg".into(), brutto_netto: 2, rechnungstyp: 0 };
+
+        // the legacy layout only knows ids
+        let expected = items().map(|item| LineItem {
+            user: item.user_id.clone(),
+            machine: item.article_id.clone(),
+            ..item
+        });
+
+        assert_eq!(round_trip(&legacy, &log(&TempDir::new("export-legacy"))), expected);
+    }
+
+    #[test]
+    fn datev_round_trip() {
+        let temp_dir = TempDir::new("export-datev");
+        let log = log(&temp_dir);
+
+        assert_eq!(round_trip(&datev(), &log), items());
+
+        let content = fs::read_to_string(&log.path).expect("billing log");
+        assert!(content.lines().nth(1).is_some_and(|row| row.starts_with("2;1,50;3;S;17;8400;;")));
+    }
+
+    #[test]
+    fn datev_refuses_rows_it_cant_book() {
+        let temp_dir = TempDir::new("export-datev-refused");
+        let log = log(&temp_dir);
+        let [alice, _bob] = items();
+
+        let by_name = LineItem { user_id: "alice".into(), ..alice.clone() };
+        let unpriced = LineItem { article_id: "7".into(), ..alice };
+
+        assert!(datev().export(&by_name, &log).is_err());
+        assert!(datev().export(&unpriced, &log).is_err());
+        assert!(!PathBuf::from(&log.path).exists());
+    }
+
+    #[test]
+    fn json_lines_round_trip() {
+        assert_eq!(round_trip(&JsonLines, &log(&TempDir::new("export-json-lines"))), items());
+    }
+
+    #[test]
+    fn json_lines_rotate_other_formats_away() {
+        let temp_dir = TempDir::new("export-json-lines-rotated");
+        let log = log(&temp_dir);
+        let [alice, bob] = items();
+
+        datev().export(&alice, &log).expect("export");
+        JsonLines.export(&bob, &log).expect("export");
+
+        let imported = JsonLines.import(Box::new(File::open(&log.path).expect("billing log")));
+        assert_eq!(imported, [bob]);
+
+        let files = PathBuf::from(&log.path).parent().expect("temp dir").read_dir().expect("temp dir").count();
+        assert_eq!(files, 2);
+    }
+}
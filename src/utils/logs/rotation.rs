@@ -0,0 +1,248 @@
+use std::io::{self, Read};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Datelike, Local};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use tracing::{debug, error, warn};
+
+use crate::config::log_file::{LogFile, Rotation};
+
+const GZIP_EXTENSION: &str = ".gz";
+const TEMPORARY_EXTENSION: &str = ".tmp";
+
+///rotates the log if its period is over or it grew too big.
+///the period is taken from the last modification, so a restart doesn't lose track of it.
+///failing to rotate only gets logged, the log just keeps growing then
+pub fn rotate_if_due(log: &LogFile) {
+    if let Err(error) = try_rotate_if_due(log) {
+        error!("failed to rotate {} ~~ {error}", log.path);
+    }
+}
+
+fn try_rotate_if_due(log: &LogFile) -> io::Result<()> {
+    let metadata = match fs::metadata(&log.path) {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(error)
+    };
+
+    if metadata.len() == 0 {
+        return Ok(());
+    }
+
+    let modified = DateTime::<Local>::from(metadata.modified()?);
+    let now = Local::now();
+
+    let label = match log.rotation {
+        Rotation::Never => None,
+        Rotation::Daily => (modified.date_naive() != now.date_naive()).then(|| modified.format("%Y-%m-%d")),
+        Rotation::Weekly => (modified.iso_week() != now.iso_week()).then(|| modified.format("%G-W%V")),
+        Rotation::Monthly => (modified.year() != now.year() || modified.month() != now.month()).then(|| modified.format("%Y-%m")),
+        Rotation::Size(limit) => (metadata.len() >= limit).then(|| now.format("%Y%m%d-%H%M%S"))
+    };
+
+    match label {
+        Some(label) => rotate(log, &label.to_string()),
+        None => Ok(())
+    }
+}
+
+///renames e.g. `billinglog.csv` to `billinglog.<label>.csv`, then compresses and cleans up as configured
+pub fn rotate(log: &LogFile, label: &str) -> io::Result<()> {
+    let path = Path::new(&log.path);
+
+    let rotated = (1..)
+        .map(|attempt| if attempt == 1 { labeled(path, label) } else { labeled(path, &format!("{label}-{attempt}")) })
+        .find(|candidate| !candidate.exists() && !compressed_path(candidate).exists())
+        .unwrap_or_default();
+
+    warn!("rotating {} to {}", path.display(), rotated.display());
+    fs::rename(path, &rotated)?;
+
+    if log.compress {
+        // big debug logs take a while, no need to hold up the caller
+        thread::spawn(move || {
+            if let Err(error) = compress(&rotated) {
+                error!("failed to compress {} ~~ {error}", rotated.display());
+            }
+        });
+    }
+
+    if let Some(retention) = log.retention
+        && let Err(error) = prune(log, retention)
+    {
+        error!("failed to delete old logs of {} ~~ {error}", path.display());
+    }
+
+    Ok(())
+}
+
+///the log itself and everything `rotate` made of it, oldest first.
+///a file that got compressed but not deleted yet only counts once
+pub fn log_files(path: &str) -> io::Result<Vec<PathBuf>> {
+    let path = Path::new(path);
+    let mut files = rotated_files(path)?;
+    files.retain(|file| !compressed_path(file).exists());
+
+    files.sort_by_key(|file| fs::metadata(file).and_then(|metadata| metadata.modified()).unwrap_or(SystemTime::UNIX_EPOCH));
+    files.extend(path.exists().then(|| path.to_owned()));
+    Ok(files)
+}
+
+///decompresses rotated logs transparently
+pub fn open_log(path: &Path) -> io::Result<Box<dyn Read>> {
+    let file = File::open(path)?;
+
+    if file_name(path).ends_with(GZIP_EXTENSION) {
+        Ok(Box::new(GzDecoder::new(file)))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+fn labeled(path: &Path, label: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    match path.extension() {
+        Some(extension) => path.with_file_name(format!("{stem}.{label}.{}", extension.to_string_lossy())),
+        None => path.with_file_name(format!("{stem}.{label}"))
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn rotated_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+    let directory = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+
+    let files = fs::read_dir(directory)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|candidate| {
+            let name = file_name(candidate);
+            let name = name.strip_suffix(GZIP_EXTENSION).unwrap_or(&name);
+
+            name.strip_prefix(&format!("{stem}."))
+                .and_then(|rest| rest.strip_suffix(&extension))
+                .is_some_and(|label| !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+        })
+        .collect();
+
+    Ok(files)
+}
+
+fn compressed_path(path: &Path) -> PathBuf {
+    path.with_file_name(format!("{}{GZIP_EXTENSION}", file_name(path)))
+}
+
+///goes through a temporary file, a half-written archive must never sit next to the original
+fn compress(path: &Path) -> io::Result<()> {
+    let compressed = compressed_path(path);
+    let temporary = path.with_file_name(format!("{}{TEMPORARY_EXTENSION}", file_name(&compressed)));
+
+    let mut encoder = GzEncoder::new(File::create(&temporary)?, Compression::default());
+    io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+
+    fs::rename(temporary, compressed)?;
+    fs::remove_file(path)
+}
+
+fn prune(log: &LogFile, retention: std::time::Duration) -> io::Result<()> {
+    // retentions beyond the epoch keep everything
+    let Some(cutoff) = SystemTime::now().checked_sub(retention) else {
+        return Ok(());
+    };
+
+    for file in rotated_files(Path::new(&log.path))? {
+        let modified = fs::metadata(&file)?.modified()?;
+        if modified < cutoff {
+            debug!("deleting {}, it's past retention", file.display());
+            fs::remove_file(file)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::time::Duration;
+
+    use crate::utils::temp_dir::TempDir;
+
+    use super::*;
+
+    ///creates the file with a modification time this many minutes ago
+    fn touch(directory: &Path, name: &str, minutes_ago: u64) -> PathBuf {
+        let path = directory.join(name);
+        let file = File::create(&path).expect("temp file");
+        file.set_modified(SystemTime::now() - Duration::from_secs(minutes_ago * 60)).expect("mtime");
+        path
+    }
+
+    fn names(files: &[PathBuf]) -> Vec<String> {
+        files.iter().map(|file| file_name(file)).collect()
+    }
+
+    #[test]
+    fn log_files_are_ordered_by_age_with_the_log_last() {
+        let temp_dir = TempDir::new("rotation-order");
+        let directory = temp_dir.path();
+        touch(directory, "machinelog.csv", 0);
+        touch(directory, "machinelog.2026-10-01.csv", 10);
+        touch(directory, "machinelog.2026-09-30.csv.gz", 20);
+        touch(directory, "machinelog.20261001-120000-2.csv", 5);
+        touch(directory, "machinelog_debug.csv", 30);
+        touch(directory, "machinelog.backup.csv.gz.tmp", 30);
+
+        let files = log_files(&directory.join("machinelog.csv").to_string_lossy()).expect("log files");
+
+        assert_eq!(names(&files), [
+            "machinelog.2026-09-30.csv.gz",
+            "machinelog.2026-10-01.csv",
+            "machinelog.20261001-120000-2.csv",
+            "machinelog.csv"
+        ]);
+    }
+
+    #[test]
+    fn log_files_count_a_file_being_compressed_once() {
+        let temp_dir = TempDir::new("rotation-compressing");
+        let directory = temp_dir.path();
+        touch(directory, "billinglog.2026-09.csv", 10);
+        touch(directory, "billinglog.2026-09.csv.gz", 5);
+
+        let files = log_files(&directory.join("billinglog.csv").to_string_lossy()).expect("log files");
+
+        assert_eq!(names(&files), ["billinglog.2026-09.csv.gz"]);
+    }
+
+    #[test]
+    fn compressed_logs_read_like_the_original() {
+        let temp_dir = TempDir::new("rotation-compress");
+        let directory = temp_dir.path();
+        let path = directory.join("machinelog.2026-10-01.csv");
+        File::create(&path).and_then(|mut file| file.write_all(b"a,b\n1,2\n")).expect("temp file");
+
+        compress(&path).expect("compress");
+
+        let mut content = String::new();
+        open_log(&compressed_path(&path)).and_then(|mut log| log.read_to_string(&mut content)).expect("compressed log");
+
+        assert_eq!(content, "a,b\n1,2\n");
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,57 @@
+use std::sync::LazyLock;
+
+use prometheus::{register_histogram, register_int_counter, register_int_counter_vec, register_int_gauge, register_int_gauge_vec};
+use prometheus::{Encoder, Histogram, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder};
+
+//gauges mirroring shared state get refreshed on every scrape, see `web::on_metrics`
+
+pub static BOOKINGS_ACTIVE: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!("spacermake_booking_active", "1 while the machine is booked", &["machine"])
+        .expect("metric registration")
+});
+
+pub static SLAVE_POWERED: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!("spacermake_slave_powered", "power state of the slave, as reported by it if it has a state topic", &["slave"])
+        .expect("metric registration")
+});
+
+pub static SCHEDULED_SHUTDOWNS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("spacermake_scheduled_shutdowns", "slave shutdowns waiting for their trailing time")
+        .expect("metric registration")
+});
+
+pub static MQTT_MESSAGES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("spacermake_mqtt_messages_total", "MQTT messages handled, by handler and outcome", &["handler", "result"])
+        .expect("metric registration")
+});
+
+pub static FABACCESS_RPC_DURATION: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!("spacermake_fabaccess_rpc_duration_seconds", "duration of FabAccess API calls, including login")
+        .expect("metric registration")
+});
+
+pub static FABACCESS_RPC_FAILURES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("spacermake_fabaccess_rpc_failures_total", "FabAccess API calls that failed")
+        .expect("metric registration")
+});
+
+pub static MACHINE_RUNTIME: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("spacermake_machine_runtime_seconds_total", "runtime of released bookings", &["machine"])
+        .expect("metric registration")
+});
+
+///everything registered so far, in the Prometheus text format
+pub fn encode() -> String {
+    // series without labels should show up before their first change
+    LazyLock::force(&SCHEDULED_SHUTDOWNS);
+    LazyLock::force(&FABACCESS_RPC_DURATION);
+    LazyLock::force(&FABACCESS_RPC_FAILURES);
+
+    let mut buffer = Vec::new();
+
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("encoding into a Vec can't fail");
+
+    String::from_utf8_lossy(&buffer).into_owned()
+}
@@ -0,0 +1,35 @@
+use std::time::Instant;
+
+///what a slave's relay is supposed to be and what it last reported
+#[derive(Debug)]
+pub struct Relay {
+    pub target: bool,
+    pub actual: Option<bool>,
+    pub commanded_at: Instant,
+    pub retries: u32,
+    pub given_up: bool
+}
+
+impl Relay {
+    pub fn commanded(target: bool) -> Self {
+        Self {
+            target,
+            actual: None,
+            commanded_at: Instant::now(),
+            retries: 0,
+            given_up: false
+        }
+    }
+
+    ///a relay nobody asked to switch, so whatever it reports is fine
+    pub fn observed(actual: bool) -> Self {
+        Self {
+            actual: Some(actual),
+            ..Self::commanded(actual)
+        }
+    }
+
+    pub fn is_confirmed(&self) -> bool {
+        self.actual == Some(self.target)
+    }
+}
@@ -0,0 +1,164 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+type Entry = (DateTime<Local>, String);
+
+///pending delayed actions with at most one deadline per key, in any order of deadlines
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(from = "Vec<Entry>", into = "Vec<Entry>")]
+pub struct Schedule {
+    deadlines: HashMap<String, DateTime<Local>>,
+    ///may contain outdated entries, which get discarded lazily by comparing against `deadlines`
+    queue: BinaryHeap<Reverse<Entry>>
+}
+
+impl Schedule {
+    ///replaces any deadline previously scheduled for this key
+    pub fn insert(&mut self, key: String, deadline: DateTime<Local>) {
+        self.deadlines.insert(key.clone(), deadline);
+        self.queue.push(Reverse((deadline, key)));
+    }
+
+    pub fn remove(&mut self, key: &str) -> bool {
+        self.deadlines.remove(key).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.deadlines.len()
+    }
+
+    pub fn next_deadline(&mut self) -> Option<DateTime<Local>> {
+        self.discard_outdated();
+        self.queue.peek().map(|Reverse((deadline, _key))| *deadline)
+    }
+
+    ///removes and returns every key whose deadline has passed, earliest first
+    pub fn pop_due(&mut self, now: DateTime<Local>) -> Vec<String> {
+        let mut due = Vec::new();
+
+        while self.next_deadline().is_some_and(|deadline| deadline <= now) {
+            let Some(Reverse((_deadline, key))) = self.queue.pop() else { break };
+            self.deadlines.remove(&key);
+            due.push(key);
+        }
+
+        due
+    }
+
+    ///removes and returns every key regardless of its deadline, earliest first
+    pub fn drain(&mut self) -> Vec<String> {
+        let mut keys = Vec::new();
+
+        while self.next_deadline().is_some() {
+            let Some(Reverse((_deadline, key))) = self.queue.pop() else { break };
+            self.deadlines.remove(&key);
+            keys.push(key);
+        }
+
+        keys
+    }
+
+    fn discard_outdated(&mut self) {
+        while let Some(Reverse((deadline, key))) = self.queue.peek() {
+            if self.deadlines.get(key) == Some(deadline) {
+                break;
+            }
+            self.queue.pop();
+        }
+    }
+}
+
+impl From<Vec<Entry>> for Schedule {
+    fn from(entries: Vec<Entry>) -> Self {
+        let mut schedule = Self::default();
+        for (deadline, key) in entries {
+            schedule.insert(key, deadline);
+        }
+        schedule
+    }
+}
+
+impl From<Schedule> for Vec<Entry> {
+    fn from(schedule: Schedule) -> Self {
+        let mut entries = schedule
+            .deadlines
+            .into_iter()
+            .map(|(key, deadline)| (deadline, key))
+            .collect::<Self>();
+
+        entries.sort();
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    fn at(seconds: i64) -> DateTime<Local> {
+        DateTime::UNIX_EPOCH.with_timezone(&Local) + TimeDelta::seconds(seconds)
+    }
+
+    #[test]
+    fn pops_due_keys_earliest_first() {
+        let mut schedule = Schedule::default();
+        schedule.insert("b".into(), at(20));
+        schedule.insert("a".into(), at(10));
+        schedule.insert("c".into(), at(30));
+
+        assert_eq!(schedule.pop_due(at(25)), ["a", "b"]);
+        assert_eq!(schedule.next_deadline(), Some(at(30)));
+        assert_eq!(schedule.len(), 1);
+    }
+
+    #[test]
+    fn rescheduling_replaces_the_deadline() {
+        let mut schedule = Schedule::default();
+        schedule.insert("a".into(), at(10));
+        schedule.insert("a".into(), at(30));
+
+        assert!(schedule.pop_due(at(20)).is_empty());
+        assert_eq!(schedule.pop_due(at(30)), ["a"]);
+    }
+
+    #[test]
+    fn removed_keys_are_never_due() {
+        let mut schedule = Schedule::default();
+        schedule.insert("a".into(), at(10));
+
+        assert!(schedule.remove("a"));
+        assert!(!schedule.remove("a"));
+        assert_eq!(schedule.next_deadline(), None);
+        assert!(schedule.pop_due(at(20)).is_empty());
+    }
+
+    #[test]
+    fn drain_ignores_deadlines() {
+        let mut schedule = Schedule::default();
+        schedule.insert("b".into(), at(20));
+        schedule.insert("a".into(), at(10));
+        schedule.insert("a".into(), at(30));
+        schedule.insert("c".into(), at(5));
+        schedule.remove("c");
+
+        assert_eq!(schedule.drain(), ["b", "a"]);
+        assert_eq!(schedule.len(), 0);
+    }
+
+    #[test]
+    fn survives_serialization() {
+        let mut schedule = Schedule::default();
+        schedule.insert("a".into(), at(10));
+        schedule.insert("b".into(), at(20));
+
+        let entries = Vec::<Entry>::from(schedule.clone());
+        let mut restored = Schedule::from(entries);
+
+        assert_eq!(restored.pop_due(at(20)), schedule.pop_due(at(20)));
+    }
+}
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use json::{object, JsonValue};
+
+///the announcer ticks every second, this much silence means it's stuck
+const ANNOUNCER_STALL: Duration = Duration::from_secs(10);
+
+///connection health, as reported on the status endpoints
+#[derive(Debug)]
+pub struct Status {
+    pub mqtt_connected: bool,
+    ///when `mqtt_connected` last changed
+    pub mqtt_since: DateTime<Local>,
+    pub mqtt_error: Option<String>,
+    pub last_message: Option<DateTime<Local>>,
+    pub announcer_tick: Option<DateTime<Local>>
+}
+
+impl Default for Status {
+    fn default() -> Self {
+        Self {
+            mqtt_connected: false,
+            mqtt_since: Local::now(),
+            mqtt_error: None,
+            last_message: None,
+            announcer_tick: None
+        }
+    }
+}
+
+impl Status {
+    pub fn set_mqtt_connected(&mut self, connected: bool, error: Option<String>) {
+        if self.mqtt_connected != connected {
+            self.mqtt_since = Local::now();
+        }
+        self.mqtt_connected = connected;
+        self.mqtt_error = error;
+    }
+
+    pub const fn is_degraded(&self) -> bool {
+        !self.mqtt_connected
+    }
+
+    pub fn is_announcer_ticking(&self) -> bool {
+        self.announcer_tick.is_some_and(|tick| {
+            (Local::now() - tick)
+                .to_std()
+                .is_ok_and(|silence| silence < ANNOUNCER_STALL)
+        })
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        object! {
+            status: if self.is_degraded() { "degraded" } else { "ok" },
+            mqtt: object! {
+                connected: self.mqtt_connected,
+                since: self.mqtt_since.to_rfc3339(),
+                error: self.mqtt_error.clone(),
+                last_message: self.last_message.map(|time| time.to_rfc3339())
+            },
+            announcer: object! {
+                ticking: self.is_announcer_ticking(),
+                last_tick: self.announcer_tick.map(|time| time.to_rfc3339())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    fn ticked(ago: TimeDelta) -> Status {
+        Status { announcer_tick: Some(Local::now() - ago), ..Status::default() }
+    }
+
+    #[test]
+    fn announcer_ticks_until_it_stalls() {
+        assert!(ticked(TimeDelta::zero()).is_announcer_ticking());
+        assert!(ticked(TimeDelta::seconds(9)).is_announcer_ticking());
+        assert!(!ticked(TimeDelta::seconds(11)).is_announcer_ticking());
+    }
+
+    #[test]
+    fn announcer_that_never_ticked_is_stuck() {
+        assert!(!Status::default().is_announcer_ticking());
+    }
+}
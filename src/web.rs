@@ -1,48 +1,263 @@
 #![allow(clippy::absolute_paths, reason = "warp")]
 
-use std::net::Ipv4Addr;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use anyhow::anyhow;
-use base64::prelude::BASE64_STANDARD;
-use base64::Engine;
-use colour::*;
+use std::time::Duration;
+use anyhow::{anyhow, Context};
 use http::*;
 use http::header::*;
+use futures::future::join;
+use json::{object, JsonValue};
+use serde::Deserialize;
 use tap::Pipe;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, timeout};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, instrument};
 use warp::filters::path::FullPath;
 use warp::reply::*;
 use warp::*;
 
 use crate::config::SpacerConfig;
+use crate::state::{State, Web};
+use crate::utils::metrics::{self, BOOKINGS_ACTIVE, SCHEDULED_SHUTDOWNS};
 
-mod fab_api;
+use self::session::Sessions;
+
+pub mod fab_api;
 mod page;
+mod session;
+mod tls;
+
+const FABACCESS_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+const SESSION_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+///serves until shutdown, then lets running requests finish
+pub async fn start(state: State<Web>, shutdown: CancellationToken) {
+    let state = Arc::new(state);
+    let healthz_state = Arc::clone(&state);
+    let readyz_state = Arc::clone(&state);
+    let metrics_state = Arc::clone(&state);
+    let sessions = Arc::new(Sessions::default());
+    let login_sessions = Arc::clone(&sessions);
+    let logout_sessions = Arc::clone(&sessions);
+    let sweep_sessions = Arc::clone(&sessions);
+    let login_state = Arc::clone(&state);
+    let sweep_state = Arc::clone(&state);
+
+    // only read once, changing these needs a restart
+    let config = state.config();
+    let address = SocketAddr::new(config.http_bind, config.http_port);
+    let monitoring_enabled = config.http_monitoring;
+
+    let healthz =
+        warp::path!("healthz")
+        .then(move || on_healthz(Arc::clone(&healthz_state)));
+
+    // `/status` predates the probes and stays as an alias
+    let readyz =
+        warp::path!("readyz")
+        .or(warp::path!("status"))
+        .unify()
+        .then(move || on_readyz(Arc::clone(&readyz_state)));
+
+    let metrics =
+        warp::path!("metrics")
+        .then(move || on_metrics(Arc::clone(&metrics_state)));
+
+    // no login needed for these, see HTTP_MONITORING
+    let monitoring =
+        warp::any()
+        .and_then(move || async move { if monitoring_enabled { Ok(()) } else { Err(warp::reject::not_found()) } })
+        .untuple_one()
+        .and(healthz.or(readyz).or(metrics));
+
+    let login =
+        warp::path!("login")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(4096))
+        .and(warp::body::form())
+        .and(warp::cookie::optional(session::COOKIE))
+        .then(move |form, token| on_login(form, token, Arc::clone(&login_sessions), login_state.config()));
+
+    let logout =
+        warp::path!("logout")
+        .and(warp::post())
+        .and(warp::cookie::optional(session::COOKIE))
+        .then(move |token| on_logout(token, Arc::clone(&logout_sessions)));
+
+    let routes =
+        path::full()
+        .and(warp::method())
+        .and(warp::cookie::optional(session::COOKIE))
+        .then(move |path, method, token| on_request(path, method, token, Arc::clone(&sessions), state.config()))
+        .pipe(|main| monitoring.or(login).or(logout).or(main))
+        .pipe(|main| warp::fs::dir(config.www_dir.clone()).or(main))
+        .map(Reply::into_response)
+        .boxed();
 
-pub async fn start(config: Arc<SpacerConfig>) {
-    path::full()
-    .and(warp::header::optional(AUTHORIZATION.as_str()))
-    .then(move |path, auth| on_request(path, auth, Arc::clone(&config)))
-    .pipe(|main| warp::fs::dir("www").or(main))
-    .pipe(warp::serve)
-    .run((Ipv4Addr::UNSPECIFIED, 80))
-    .await;
+    let sweep = async {
+        loop {
+            sleep(SESSION_SWEEP_INTERVAL).await;
+            sweep_sessions.expire(sweep_state.config().session_timeout).await;
+        }
+    };
+
+    let serve = async {
+        let listener = TcpListener::bind(address)
+            .await
+            .with_context(|| format!("can't listen on {address}"))?;
+
+        match &config.http_tls {
+            Some(http_tls) => {
+                info!("serving web UI on https://{address}");
+                tls::serve(routes, listener, http_tls, shutdown.clone()).await
+            }
+            None => {
+                info!("serving web UI on http://{address}");
+                warp::serve(routes)
+                .incoming(listener)
+                .graceful(shutdown.clone().cancelled_owned())
+                .run()
+                .await;
+                Ok(())
+            }
+        }
+    };
+
+    // the rest of spacermake keeps running without the web UI
+    let (served, _) = join(serve, shutdown.run_until_cancelled(sweep)).await;
+    if let Err(error) = served {
+        error!("web UI unavailable ~~ {error:#}");
+    }
+    info!("web server stopped");
 }
 
-async fn on_request(path: FullPath, auth: Option<String>, config: Arc<SpacerConfig>) -> warp::reply::Response {
-	try_handle(path, auth, &config)
+#[instrument(skip_all, fields(path = path.as_str()))]
+async fn on_request(path: FullPath, method: Method, token: Option<String>, sessions: Arc<Sessions>, config: Arc<SpacerConfig>) -> warp::reply::Response {
+	try_handle(path, &method, token, &sessions, &config)
     .await
     .unwrap_or_else(|err| {
-        if format!("{err:?}") == "(code = invalidCredentials)" {
-            reply().with_auth().into_response()
-        } else {
-            red_ln!("{err:#?}");
+        error!("{err:#?}");
+        page::error(&err)
+    })
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    username: String,
+    password: String,
+    next: Option<String>
+}
+
+#[instrument(skip_all, fields(username = form.username))]
+async fn on_login(form: LoginForm, token: Option<String>, sessions: Arc<Sessions>, config: Arc<SpacerConfig>) -> warp::reply::Response {
+    let next = form.next.as_deref().map_or("/", local_path);
+
+    // the cookie gets replaced, so the session it pointed to would linger unreachable
+    if let Some(token) = token {
+        sessions.remove(&token).await;
+    }
+
+    match fab_api::Session::login(&form.username, &form.password, &config).await {
+        Ok(fabaccess) => {
+            let token = sessions.create(form.username, fabaccess).await;
+            info!("logged in");
+
+            redirect(next)
+            .with_header(SET_COOKIE.as_str(), &session_cookie(&token, config.http_tls.is_some()))
+            .into_response()
+        }
+        Err(err) if err.to_string() == "(code = invalidCredentials)" => {
+            page::login(next, true)
+            .pipe_ref(page::template)
+            .with_status(StatusCode::UNAUTHORIZED)
+            .into_response()
+        }
+        Err(err) => {
+            error!("{err:#?}");
             page::error(&err)
         }
-    })
+    }
 }
 
-async fn try_handle(path: FullPath, auth: Option<String>, config: &Arc<SpacerConfig>) -> anyhow::Result<warp::reply::Response> {
-    yellow_ln!("{}", path.as_str());
+#[instrument(skip_all)]
+async fn on_logout(token: Option<String>, sessions: Arc<Sessions>) -> warp::reply::Response {
+    if let Some(token) = token {
+        sessions.remove(&token).await;
+    }
+
+    redirect("/")
+    .with_header(SET_COOKIE.as_str(), &format!("{}=; Path=/; Max-Age=0", session::COOKIE))
+    .into_response()
+}
+
+///liveness: fails only if the announcer loop is stuck, which a restart would fix
+#[instrument(skip_all)]
+async fn on_healthz(state: Arc<State<Web>>) -> warp::reply::Response {
+    let status = state.status.read().await;
+    let alive = status.is_announcer_ticking();
+
+    status
+    .to_json()
+    .tap_status(alive)
+    .pipe(|json| health_response(json, alive))
+}
+
+///readiness: MQTT connected, announcer ticking and FabAccess reachable
+#[instrument(skip_all)]
+async fn on_readyz(state: Arc<State<Web>>) -> warp::reply::Response {
+    let fabaccess_error = check_fabaccess(&state.config()).await;
+    let status = state.status.read().await;
+    let ready = !status.is_degraded() && status.is_announcer_ticking() && fabaccess_error.is_none();
+
+    let mut json = status.to_json().tap_status(ready);
+    json["fabaccess"] = object! {
+        reachable: fabaccess_error.is_none(),
+        error: fabaccess_error
+    };
+
+    health_response(json, ready)
+}
+
+///why FabAccess can't be reached, if it can't.
+///only opens a TCP connection, logging in would need credentials
+async fn check_fabaccess(config: &SpacerConfig) -> Option<String> {
+    let address = (config.fabaccess_host.as_str(), config.fabaccess_port);
+
+    match timeout(FABACCESS_CHECK_TIMEOUT, TcpStream::connect(address)).await {
+        Ok(Ok(_)) => None,
+        Ok(Err(error)) => Some(error.to_string()),
+        Err(_) => Some(format!("no connection within {}", humantime::format_duration(FABACCESS_CHECK_TIMEOUT)))
+    }
+}
+
+fn health_response(json: JsonValue, healthy: bool) -> warp::reply::Response {
+    let code = if healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+
+    json
+    .dump()
+    .with_header(CONTENT_TYPE.as_str(), "application/json")
+    .with_status(code)
+    .into_response()
+}
+
+#[instrument(skip_all)]
+async fn on_metrics(state: Arc<State<Web>>) -> warp::reply::Response {
+    BOOKINGS_ACTIVE.reset();
+    for machine in state.bookings.read().await.keys() {
+        BOOKINGS_ACTIVE.with_label_values(&[machine]).set(1);
+    }
+
+    SCHEDULED_SHUTDOWNS.set(state.scheduled_shutdowns.read().await.len() as _);
+
+    metrics::encode()
+    .with_header(CONTENT_TYPE.as_str(), "text/plain; version=0.0.4")
+    .into_response()
+}
+
+async fn try_handle(path: FullPath, method: &Method, token: Option<String>, sessions: &Sessions, config: &SpacerConfig) -> anyhow::Result<warp::reply::Response> {
+    debug!("handling request");
     let path =
         path
         .as_str()
@@ -53,22 +268,44 @@ async fn try_handle(path: FullPath, auth: Option<String>, config: &Arc<SpacerCon
         return Ok(http::StatusCode::NO_CONTENT.into_response());
     }
     
-    let Some(auth) = auth
+    let session = match &token {
+        Some(token) => sessions.get(token, config.session_timeout).await,
+        None => None
+    };
+
+    let Some(session) = session
     else {
-        return Ok(StatusCode::UNAUTHORIZED.with_auth().into_response());
+        return page::login(&format!("/{path}"), false)
+        .pipe_ref(page::template)
+        .pipe(Ok);
     };
 
-	let [username, password] =
-        auth
-        .trim_start_matches("Basic ")
-        .pipe(decode_auth)?;
+    if path == "login" {
+        return Ok(redirect("/"));
+    }
 
     let mut splits = path.split('/').filter(|split| !split.is_empty());
 
     let target = splits.next();
     let toggle = splits.next() == Some("toggle");
 
-    let resources = fab_api::get_resources(&username, &password, target.filter(|_| toggle), config).await?;
+    // toggling on a GET would let any link switch machines
+    if toggle && method != Method::POST {
+        return Ok(redirect(&format!("/{}", target.unwrap_or_default())));
+    }
+
+    let resources = match session.get_resources(target.filter(|_| toggle)).await {
+        Err(_) if session.is_closed() => {
+            // FabAccess went away, logging in again opens a new connection
+            if let Some(token) = &token {
+                sessions.remove(token).await;
+            }
+            return page::login(&format!("/{path}"), false)
+            .pipe_ref(page::template)
+            .pipe(Ok);
+        }
+        result => result?
+    };
 
     let Some(target_urn) = target
     else {
@@ -95,13 +332,19 @@ async fn try_handle(path: FullPath, auth: Option<String>, config: &Arc<SpacerCon
     }.pipe(Ok)
 }
 
-fn decode_auth(base64: &str) -> anyhow::Result<[String; 2]> {
-    BASE64_STANDARD
-    .decode(base64)?
-    .pipe(String::from_utf8)?
-    .split_once(':').ok_or_else(||anyhow!("couldn't split decoded credentials"))?
-    .pipe(|(name, pw)| [name.to_owned(), pw.to_owned()])
-    .pipe(Ok)
+///`Secure` only works over HTTPS, the browser would drop the cookie otherwise
+fn session_cookie(token: &str, secure: bool) -> String {
+    let secure = if secure { "; Secure" } else { "" };
+    format!("{}={token}; Path=/; HttpOnly; SameSite=Strict{secure}", session::COOKIE)
+}
+
+///keeps the redirect after logging in on this site. a toggle only leads to its resource, logging in must not switch anything
+fn local_path(next: &str) -> &str {
+    if next.starts_with('/') && !next.starts_with("//") && !next.starts_with("/\\") && next != "/login" {
+        next.strip_suffix("/toggle").unwrap_or(next)
+    } else {
+        "/"
+    }
 }
 
 fn redirect(location: &str) -> warp::reply::Response {
@@ -110,6 +353,14 @@ fn redirect(location: &str) -> warp::reply::Response {
     .into_response()
 }
 
+#[extend::ext]
+impl JsonValue {
+    fn tap_status(mut self, healthy: bool) -> Self {
+        self["status"] = if healthy { "ok" } else { "degraded" }.into();
+        self
+    }
+}
+
 #[extend::ext]
 impl<T: Reply> T {
     fn with_header(self, name: &str, value: &str) -> WithHeader<T> {
@@ -119,10 +370,27 @@ impl<T: Reply> T {
     fn with_status(self, status: StatusCode) -> WithStatus<T> {
         with_status(self, status)
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_path_keeps_paths_on_this_site() {
+        assert_eq!(local_path("/"), "/");
+        assert_eq!(local_path("/urn:fabaccess:resource:Laser"), "/urn:fabaccess:resource:Laser");
+    }
 
-    fn with_auth(self) -> WithHeader<WithStatus<T>> {
-	    self
-	    .with_status(StatusCode::UNAUTHORIZED)
-		.with_header(WWW_AUTHENTICATE.as_str(), "Basic")
+    #[test]
+    fn local_path_never_leads_to_a_toggle() {
+        assert_eq!(local_path("/urn:fabaccess:resource:Laser/toggle"), "/urn:fabaccess:resource:Laser");
+    }
+
+    #[test]
+    fn local_path_rejects_other_sites_and_the_login() {
+        for next in ["https://example.com", "//example.com", "/\\example.com", "example.com", "", "/login"] {
+            assert_eq!(local_path(next), "/", "{next}");
+        }
     }
 }
@@ -1,18 +1,25 @@
 use std::sync::Arc;
+use std::thread;
 
 use anyhow::anyhow;
 use capnp_rpc::rpc_twoparty_capnp::Side;
 use futures::io::{BufReader, BufWriter};
 use futures::AsyncReadExt;
-use futures::executor::block_on;
 use itertools::Itertools;
+use prometheus::HistogramTimer;
 use tokio::net::TcpStream;
+use tokio::runtime;
+use tokio::select;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
 use tokio_util::compat::TokioAsyncReadCompatExt;
 use async_native_tls::TlsConnector;
-use tap::{Tap, Pipe};
+use tap::{Tap, TapFallible, Pipe};
 use capnp_rpc::*;
 use tokio::task;
+use tracing::{debug, info, instrument, Instrument, Span};
 use crate::config::SpacerConfig;
+use crate::utils::metrics::{FABACCESS_RPC_DURATION, FABACCESS_RPC_FAILURES};
 use crate::schema::machine_capnp::machine::MachineState;
 use crate::schema::*;
 
@@ -25,43 +32,163 @@ type MachineSystemInfo = machinesystem_capnp::machine_system::info::Client;
 type AuthRespWhich<A0, A1, A2> = authenticationsystem_capnp::response::Which<A0, A1, A2>;
 type OptionalWhich<A0> = general_capnp::optional::Which<A0>;
 
+///a logged-in FabAccess connection that can be reused across requests.
+///capnp clients can't leave their thread, so each session gets a thread of its own and is sent commands.
+///dropping the session logs out
+#[derive(Debug)]
+pub struct Session {
+    commands: mpsc::UnboundedSender<Command>
+}
+
+#[derive(Debug)]
+struct Command {
+    to_toggle: Option<String>,
+    reply: oneshot::Sender<anyhow::Result<Vec<Machine>>>
+}
+
+impl Session {
+    #[instrument(skip(password, config))]
+    pub async fn login(username: &str, password: &str, config: &SpacerConfig) -> anyhow::Result<Self> {
+        let username = username.to_owned();
+        let password = password.to_owned();
+        let host = config.fabaccess_host.clone();
+        let port = config.fabaccess_port;
+        let (commands, receiver) = mpsc::unbounded_channel();
+        let (logged_in, login_result) = oneshot::channel();
+        let span = Span::current(); // the session thread doesn't inherit it
+        let timer = FABACCESS_RPC_DURATION.start_timer();
+
+        thread::Builder::new()
+            .name("fabaccess-session".to_owned()) // the username is user input
+            .spawn(move || {
+                let runtime = match runtime::Builder::new_current_thread().enable_all().build() {
+                    Ok(runtime) => runtime,
+                    Err(error) => {
+                        let _ = logged_in.send(Err(error.into()));
+                        return;
+                    }
+                };
+
+                task::LocalSet
+                ::new()
+                .block_on(&runtime, run_session(&username, &password, (&host, port), logged_in, receiver).instrument(span));
+            })?;
+
+        login_result
+        .await
+        .unwrap_or_else(|_| Err(anyhow!("FabAccess session thread died")))
+        .pipe(|result| measured(timer, result))
+        .map(|()| Self { commands })
+    }
+
+    ///toggles `to_toggle` first, if given
+    pub async fn get_resources(&self, to_toggle: Option<&str>) -> anyhow::Result<Vec<Machine>> {
+        let (reply, response) = oneshot::channel();
+        let timer = FABACCESS_RPC_DURATION.start_timer();
+
+        self.commands
+            .send(Command { to_toggle: to_toggle.map(str::to_owned), reply })
+            .map_err(|_| anyhow!("FabAccess session closed"))?;
+
+        response
+        .await
+        .unwrap_or_else(|_| Err(anyhow!("FabAccess session closed")))
+        .pipe(|result| measured(timer, result))
+    }
+
+    ///true once the connection to FabAccess is gone, the session is useless then
+    pub fn is_closed(&self) -> bool {
+        self.commands.is_closed()
+    }
+}
+
+///logs in just for this one call
 pub async fn get_resources(username: &str, password: &str, to_toggle: Option<&str>, config: &Arc<SpacerConfig>) -> anyhow::Result<Vec<Machine>> {
-    let username = username.to_owned();
-    let password = password.to_owned();
-    let to_toggle = to_toggle.map(str::to_owned);
-    let config = Arc::clone(config);
-
-    task::spawn_blocking(move || {
-        task::LocalSet
-        ::new()
-        .run_until(do_rpc(&username, &password, to_toggle.as_deref(), &config))
-        .pipe(block_on)
-    })
+    Session
+    ::login(username, password, config)
     .await?
+    .get_resources(to_toggle)
+    .await
+}
+
+fn measured<T>(timer: HistogramTimer, result: anyhow::Result<T>) -> anyhow::Result<T> {
+    timer.observe_duration();
+    if result.is_err() {
+        FABACCESS_RPC_FAILURES.inc();
+    }
+
+    result
+}
+
+///serves commands until the session gets dropped or the connection breaks
+async fn run_session(
+    username: &str,
+    password: &str,
+    server: (&str, u16),
+    logged_in: oneshot::Sender<anyhow::Result<()>>,
+    mut commands: mpsc::UnboundedReceiver<Command>
+) {
+    let (machine_system_info, mut connection) = match connect(username, password, server).await {
+        Ok(connected) => connected,
+        Err(error) => {
+            let _ = logged_in.send(Err(error));
+            return;
+        }
+    };
+
+    debug!("logged in");
+    if logged_in.send(Ok(())).is_err() {
+        return;
+    }
+
+    loop {
+        let command = select! {
+            command = commands.recv() => command,
+            result = &mut connection => {
+                info!("FabAccess connection closed ~~ {result:?}");
+                return;
+            }
+        };
+
+        let Some(Command { to_toggle, reply }) = command else {
+            debug!("logged out");
+            return;
+        };
+
+        // nobody listens if the request got cancelled meanwhile
+        let _ = reply.send(do_rpc(&machine_system_info, to_toggle.as_deref()).await);
+    }
 }
 
-async fn do_rpc(username: &str, password: &str, to_toggle: Option<&str>, config: &SpacerConfig) -> anyhow::Result<Vec<Machine>> {
-    let mut rpc_system = connect_rpc(config).await?;
+async fn connect(username: &str, password: &str, server: (&str, u16)) -> anyhow::Result<(MachineSystemInfo, JoinHandle<capnp::Result<()>>)> {
+    let mut rpc_system = connect_rpc(server).await?;
     let bootstrap = rpc_system.bootstrap::<Bootstrap>(Side::Server);
-    task::spawn_local(rpc_system);
+    let connection = task::spawn_local(rpc_system);
 
-    let machine_system_info = try_api_login(&bootstrap, username, password).await?;
+    try_api_login(&bootstrap, username, password)
+    .await?
+    .pipe(|machine_system_info| Ok((machine_system_info, connection)))
+}
 
+async fn do_rpc(machine_system_info: &MachineSystemInfo, to_toggle: Option<&str>) -> anyhow::Result<Vec<Machine>> {
     if let Some(target) = to_toggle {
-        toggle_machine(&machine_system_info, target).await?;
+        info!("toggling {target}");
+        toggle_machine(machine_system_info, target).await?;
     }
 
-    get_machines(&machine_system_info).await
+    get_machines(machine_system_info)
+    .await
+    .tap_ok(|machines| debug!("got {} resources", machines.len()))
 }
 
-async fn connect_rpc(config: &SpacerConfig) -> anyhow::Result<RpcSystem<Side>> {
-    let stream = TcpStream::connect((config.fabaccess_host.as_str(), config.fabaccess_port)).await?;
+async fn connect_rpc((host, port): (&str, u16)) -> anyhow::Result<RpcSystem<Side>> {
+    let stream = TcpStream::connect((host, port)).await?;
     stream.set_nodelay(true)?;
     
     TlsConnector
     ::new()
     .danger_accept_invalid_certs(true)
-    .connect(&config.fabaccess_host, stream)
+    .connect(host, stream)
     .await?
     .pipe(TokioAsyncReadCompatExt::compat)
     .split()
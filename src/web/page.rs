@@ -2,11 +2,13 @@ use maud::*;
 
 mod debug;
 mod error;
+mod login;
 mod overview;
 mod resource;
 
 pub use debug::debug;
 pub use error::error;
+pub use login::login;
 pub use overview::overview;
 pub use resource::resource;
 use tap::Pipe;
@@ -30,4 +32,21 @@ fn button(text: &str, dst: &str, class: &str) -> Markup {
             button type="submit" class=(class) { (text) }
         }
     }
+}
+
+///for anything that changes state, a GET could be triggered by a mere link
+fn post_button(text: &str, dst: &str, class: &str) -> Markup {
+    html! {
+        form method="post" action=(dst) {
+            button type="submit" class=(class) { (text) }
+        }
+    }
+}
+
+fn logout_button() -> Markup {
+    html! {
+        form method="post" action="/logout" {
+            button type="submit" class="logout" { "Abmelden" }
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,22 @@
+use maud::*;
+
+///`next` is where to go after logging in
+pub fn login(next: &str, failed: bool) -> Markup {
+	html! {
+		header {}
+
+		main class="login" {
+			form method="post" action="/login" {
+				input type="hidden" name="next" value=(next);
+				input type="text" name="username" placeholder="Benutzername" autocomplete="username" required autofocus;
+				input type="password" name="password" placeholder="Passwort" autocomplete="current-password" required;
+
+				@if failed {
+					p class="login-failed" { "Benutzername oder Passwort falsch" }
+				}
+
+				button type="submit" { "Anmelden" }
+			}
+		}
+	}
+}
@@ -1,7 +1,7 @@
 use itertools::Itertools;
 use maud::*;
 use crate::web::fab_api::object::{Machine, Usage};
-use crate::web::page::button;
+use crate::web::page::{button, logout_button};
 
 pub fn overview(resources: &[Machine], hide_unbooked: bool) -> Markup {
     let group_map =
@@ -15,7 +15,7 @@ pub fn overview(resources: &[Machine], hide_unbooked: bool) -> Markup {
         .collect_vec();
     
     html! {
-        header {}
+        header { (logout_button()) }
 
         main class="overview" {
             details {
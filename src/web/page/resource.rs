@@ -1,13 +1,16 @@
 use maud::*;
 
-use super::button;
+use super::{button, logout_button, post_button};
 use crate::web::fab_api::object::Machine;
 
 pub fn resource(resource: &Machine) -> Markup {
 	let status_class = format!("status-{:?}", resource.usage);
 	
 	html! {
-		header { (button("<--", "/", "back")) }
+		header {
+			(button("<--", "/", "back"))
+			(logout_button())
+		}
 		
 		main class="resource" {
 			h2 { (resource.name) }
@@ -15,7 +18,7 @@ pub fn resource(resource: &Machine) -> Markup {
 			
 			h1 class=(status_class) {}
 
-			(button("", &format!("/{}/toggle", resource.urn), &status_class))
+			(post_button("", &format!("/{}/toggle", resource.urn), &status_class))
 		}
 	}
 }
\ No newline at end of file
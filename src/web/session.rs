@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use base64::Engine;
+use tap::Pipe;
+use tokio::sync::Mutex;
+use tracing::debug;
+
+use super::fab_api;
+
+pub const COOKIE: &str = "spacermake_session";
+
+///each one holds a thread and a FabAccess connection, logging in again drops the oldest
+const MAX_SESSIONS_PER_USER: usize = 5;
+
+///logged-in web users by the token in their cookie
+#[derive(Debug, Default)]
+pub struct Sessions {
+    sessions: Mutex<HashMap<String, Session>>
+}
+
+#[derive(Debug)]
+struct Session {
+    username: String,
+    fabaccess: Arc<fab_api::Session>,
+    last_used: Instant
+}
+
+impl Sessions {
+    ///returns the token for the cookie
+    pub async fn create(&self, username: String, fabaccess: fab_api::Session) -> String {
+        let token = rand::random::<[u8; 32]>().pipe(|bytes| BASE64_URL_SAFE_NO_PAD.encode(bytes));
+        let mut sessions = self.sessions.lock().await;
+
+        while let Some(oldest) = oldest_beyond_limit(&sessions, &username) {
+            debug!("too many sessions of {username}, dropping the oldest");
+            sessions.remove(&oldest);
+        }
+
+        let session = Session {
+            username,
+            fabaccess: Arc::new(fabaccess),
+            last_used: Instant::now()
+        };
+
+        sessions.insert(token.clone(), session);
+        token
+    }
+
+    ///the FabAccess session belonging to the token, unless it expired. every use postpones the expiry
+    pub async fn get(&self, token: &str, timeout: Duration) -> Option<Arc<fab_api::Session>> {
+        let mut sessions = self.sessions.lock().await;
+        let session = sessions.get_mut(token)?;
+
+        if session.last_used.elapsed() >= timeout {
+            debug!("session of {} expired", session.username);
+            sessions.remove(token);
+            return None;
+        }
+
+        session.last_used = Instant::now();
+        Some(Arc::clone(&session.fabaccess))
+    }
+
+    ///dropping a session logs it out of FabAccess, so this has to run even without requests coming in
+    pub async fn expire(&self, timeout: Duration) {
+        self.sessions.lock().await.retain(|_token, session| {
+            let expired = session.last_used.elapsed() >= timeout;
+            if expired {
+                debug!("session of {} expired", session.username);
+            }
+            !expired
+        });
+    }
+
+    pub async fn remove(&self, token: &str) {
+        if let Some(session) = self.sessions.lock().await.remove(token) {
+            debug!("session of {} ended", session.username);
+        }
+    }
+}
+
+///the least recently used session of the user, if a new one would exceed the limit
+fn oldest_beyond_limit(sessions: &HashMap<String, Session>, username: &str) -> Option<String> {
+    let of_user = sessions
+        .iter()
+        .filter(|(_token, session)| session.username == username)
+        .collect::<Vec<_>>();
+
+    if of_user.len() < MAX_SESSIONS_PER_USER {
+        return None;
+    }
+
+    of_user
+        .into_iter()
+        .min_by_key(|(_token, session)| session.last_used)
+        .map(|(token, _session)| token.clone())
+}
@@ -0,0 +1,82 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
+use tap::{Pipe, Tap};
+use tokio::net::TcpListener;
+use tokio::select;
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error};
+use warp::filters::BoxedFilter;
+use warp::reply::Response;
+
+use crate::config::HttpTls;
+
+///clients get this long to finish the handshake, stalled ones would hold a task forever otherwise
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+///warp 0.4 dropped its TLS support, so connections get accepted and decrypted here and then handed to warp
+pub async fn serve(routes: BoxedFilter<(Response,)>, listener: TcpListener, tls: &HttpTls, shutdown: CancellationToken) -> anyhow::Result<()> {
+    let acceptor = acceptor(tls)?;
+    let graceful = GracefulShutdown::new();
+
+    loop {
+        let accepted = select! {
+            accepted = listener.accept() => accepted,
+            () = shutdown.cancelled() => break
+        };
+
+        let (stream, peer) = match accepted {
+            Ok(connection) => connection,
+            Err(error) => {
+                error!("failed to accept connection ~~ {error}");
+                continue;
+            }
+        };
+
+        let acceptor = acceptor.clone();
+        let service = TowerToHyperService::new(warp::service(routes.clone()));
+        let watcher = graceful.watcher();
+
+        tokio::spawn(async move {
+            let stream = match timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(error)) => {
+                    debug!("TLS handshake with {peer} failed ~~ {error}");
+                    return;
+                }
+                Err(_) => {
+                    debug!("TLS handshake with {peer} timed out");
+                    return;
+                }
+            };
+
+            let builder = Builder::new(TokioExecutor::new());
+            let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+
+            if let Err(error) = watcher.watch(connection).await {
+                debug!("connection to {peer} failed ~~ {error}");
+            }
+        });
+    }
+
+    drop(listener);
+    graceful.shutdown().await;
+    Ok(())
+}
+
+fn acceptor(tls: &HttpTls) -> anyhow::Result<TlsAcceptor> {
+    tls
+    .server_config()
+    .map_err(|error| anyhow!(error))?
+    .tap_mut(|config| config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()])
+    .pipe(Arc::new)
+    .pipe(TlsAcceptor::from)
+    .pipe(Ok)
+}